@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use gpio::duo::DuoFileSystem;
 use gpio::gpio_sysfs::GpioSysfs;
+use gpio::Device;
 use signal_hook::consts::SIGINT;
 use signal_hook::iterator::Signals;
 
@@ -12,13 +13,8 @@ fn main() -> anyhow::Result<()> {
     let should_terminate = Arc::new(AtomicBool::new(false));
     setup_signal_handler(should_terminate.clone())?;
 
-    // milk-v duo 64m.
-    let gpio_pin = 440;
-
-    // milk-v duo 256m.
-    // let gpio_pin = 354;
-
-    let gpio = GpioSysfs::new(gpio_pin, DuoFileSystem)?;
+    // Use Device::Duo256M on the milk-v duo 256m.
+    let gpio = GpioSysfs::open(Device::Duo, "LED", DuoFileSystem)?;
     gpio.set_pin_mode_output()?;
 
     while !should_terminate.load(Ordering::SeqCst) {
@@ -4,9 +4,8 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use gpio::duo::MilkVDuoGpio;
-use gpio::Gpio;
 use gpio::GpioDirection::GpioOutput;
-use gpio::GpioPort::Port2;
+use gpio::{Device, Gpio};
 use signal_hook::consts::SIGINT;
 use signal_hook::iterator::Signals;
 
@@ -18,10 +17,7 @@ fn main() -> anyhow::Result<()> {
     let should_terminate = Arc::new(AtomicBool::new(false));
     setup_signal_handler(should_terminate.clone())?;
 
-    // https://milkv.io/docs/duo/getting-started/duo#duo-gpio-pinout
-    // XGPIOC[24]
-    let pin = 24;
-    let duo_gpio: MilkVDuoGpio = Gpio::new(Port2, pin)?;
+    let duo_gpio: MilkVDuoGpio = Gpio::open(Device::Duo, "LED")?;
 
     // Enable LED (set GPIO to output mode)
     duo_gpio.init(GpioOutput)?;
@@ -0,0 +1,226 @@
+//! Analog inputs on the CV1800B's SARADC.
+//!
+//! The ADC has three 12-bit channels, ADC1 to ADC3. When the kernel driver
+//! is loaded they are read through IIO sysfs with [`AdcIio`], otherwise
+//! [`AdcRegisters`] runs conversions on the controller directly. Both
+//! implement [`Adc`]; [`open`] picks whichever is available.
+
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::gpio_mmap::MmioRegion;
+use crate::{FileSystemOps, GpioError, RegisterIo, Result};
+
+pub const SARADC_BASE: usize = 0x030f0000;
+const SARADC_LEN: usize = 0x100;
+
+const SARADC_CTRL: usize = 0x04;
+const SARADC_EN: u32 = 1 << 0;
+const SARADC_STATUS: usize = 0x0c;
+const SARADC_BUSY: u32 = 1 << 0;
+const SARADC_RESULT: usize = 0x14;
+const SARADC_RESULT_VALID: u32 = 1 << 15;
+const SARADC_RESULT_MASK: u32 = 0xfff;
+/// Conversions take a few microseconds; polling longer means the
+/// controller is not clocked.
+const CONVERSION_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// Full scale of the SARADC in millivolts, as the kernel driver reports it.
+pub const SARADC_REFERENCE_MV: f64 = 3300.0;
+pub const SARADC_MAX_RAW: u32 = 0xfff;
+
+const IIO_PATH: &str = "/sys/bus/iio/devices";
+
+/// One of the SARADC inputs. The Duo brings ADC1 out on GP26.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AdcChannel(u32);
+
+impl AdcChannel {
+    pub fn new(channel: u32) -> Result<Self> {
+        if (1..=3).contains(&channel) {
+            Ok(Self(channel))
+        } else {
+            Err(GpioError::InvalidAdcChannel { channel })
+        }
+    }
+
+    /// Channel number, the N in ADCN.
+    pub fn number(&self) -> u32 {
+        self.0
+    }
+
+    /// Index of the channel in IIO and in the controller, counting from 0.
+    pub fn index(&self) -> u32 {
+        self.0 - 1
+    }
+}
+
+pub trait Adc {
+    fn read_raw(&self, channel: AdcChannel) -> Result<u32>;
+    /// Millivolts per count.
+    fn scale_mv(&self, channel: AdcChannel) -> Result<f64>;
+
+    fn read_mv(&self, channel: AdcChannel) -> Result<f64> {
+        Ok(f64::from(self.read_raw(channel)?) * self.scale_mv(channel)?)
+    }
+
+    /// Mean of `samples` back-to-back conversions, in counts.
+    fn read_average(&self, channel: AdcChannel, samples: usize) -> Result<f64> {
+        let samples = samples.max(1);
+        let mut sum = 0u64;
+        for _ in 0..samples {
+            sum += u64::from(self.read_raw(channel)?);
+        }
+
+        Ok(sum as f64 / samples as f64)
+    }
+
+    fn read_average_mv(&self, channel: AdcChannel, samples: usize) -> Result<f64> {
+        Ok(self.read_average(channel, samples)? * self.scale_mv(channel)?)
+    }
+
+    /// Fills `buf` with conversions taken `rate_hz` times a second. Sample
+    /// times are kept on a fixed schedule from the first one, so a late
+    /// sample does not delay the rest.
+    fn sample(&self, channel: AdcChannel, rate_hz: f64, buf: &mut [u32]) -> Result<()> {
+        if !(rate_hz > 0.0 && rate_hz.is_finite()) {
+            return Err(GpioError::InvalidSampleRate { rate_hz });
+        }
+        let period = Duration::from_secs_f64(1.0 / rate_hz);
+        let start = Instant::now();
+        for (i, sample) in buf.iter_mut().enumerate() {
+            let deadline = start + period.mul_f64(i as f64);
+            if let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            *sample = self.read_raw(channel)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The SARADC through an IIO device directory, e.g.
+/// `/sys/bus/iio/devices/iio:device0`.
+pub struct AdcIio<F: FileSystemOps> {
+    dir: PathBuf,
+    fs_ops: F,
+}
+
+impl<F: FileSystemOps> AdcIio<F> {
+    /// Uses `/sys/bus/iio/devices/iio:device{device}`.
+    pub fn new(device: u32, fs_ops: F) -> Self {
+        Self::with_path(Path::new(IIO_PATH).join(format!("iio:device{device}")), fs_ops)
+    }
+
+    pub fn with_path(dir: impl AsRef<Path>, fs_ops: F) -> Self {
+        Self { dir: dir.as_ref().to_path_buf(), fs_ops }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    fn read_attr<T: std::str::FromStr>(&self, attr: &str) -> Result<T> {
+        let path = self.dir.join(attr);
+        let value = self.fs_ops.read_to_string(&path)?;
+        value.trim().parse().map_err(|_| GpioError::InvalidValue { path, value })
+    }
+}
+
+impl<F: FileSystemOps> Adc for AdcIio<F> {
+    fn read_raw(&self, channel: AdcChannel) -> Result<u32> {
+        self.read_attr(&format!("in_voltage{}_raw", channel.index()))
+    }
+
+    /// Prefers the channel's own `in_voltageN_scale` over the shared
+    /// `in_voltage_scale`.
+    fn scale_mv(&self, channel: AdcChannel) -> Result<f64> {
+        self.read_attr(&format!("in_voltage{}_scale", channel.index()))
+            .or_else(|_| self.read_attr("in_voltage_scale"))
+    }
+}
+
+/// The SARADC driven through its registers, one conversion at a time.
+pub struct AdcRegisters<R: RegisterIo = MmioRegion> {
+    regs: R,
+    reference_mv: f64,
+}
+
+impl<R: RegisterIo> AdcRegisters<R> {
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_registers(R::map(SARADC_BASE, SARADC_LEN)?))
+    }
+
+    pub fn with_registers(regs: R) -> Self {
+        Self { regs, reference_mv: SARADC_REFERENCE_MV }
+    }
+
+    /// Calibrates the full-scale voltage, e.g. against a measured
+    /// reference.
+    pub fn with_reference_mv(mut self, reference_mv: f64) -> Self {
+        self.reference_mv = reference_mv;
+        self
+    }
+
+    pub fn registers(&self) -> &R {
+        &self.regs
+    }
+
+    fn convert(&self, channel: AdcChannel) -> Result<u32> {
+        let select = 1 << (channel.index() + 5);
+        self.regs.write32(SARADC_BASE + SARADC_CTRL, select | SARADC_EN)?;
+
+        let start = Instant::now();
+        while self.regs.read32(SARADC_BASE + SARADC_STATUS)? & SARADC_BUSY != 0 {
+            if start.elapsed() > CONVERSION_TIMEOUT {
+                return Err(GpioError::AdcTimeout { channel: channel.number() });
+            }
+            thread::yield_now();
+        }
+
+        let result =
+            self.regs.read32(SARADC_BASE + SARADC_RESULT + channel.index() as usize * 4)?;
+        if result & SARADC_RESULT_VALID == 0 {
+            return Err(GpioError::AdcTimeout { channel: channel.number() });
+        }
+
+        Ok(result & SARADC_RESULT_MASK)
+    }
+}
+
+impl<R: RegisterIo> Adc for AdcRegisters<R> {
+    fn read_raw(&self, channel: AdcChannel) -> Result<u32> {
+        let result = self.convert(channel);
+        // Stop the controller even if the conversion failed.
+        let stopped = self.regs.write32(SARADC_BASE + SARADC_CTRL, 0);
+
+        result.and_then(|raw| stopped.map(|()| raw))
+    }
+
+    fn scale_mv(&self, _channel: AdcChannel) -> Result<f64> {
+        Ok(self.reference_mv / f64::from(SARADC_MAX_RAW + 1))
+    }
+}
+
+/// Opens the SARADC through IIO on `fs_ops` if the kernel driver is bound
+/// to it, and through `/dev/mem` otherwise. Pass [`DuoFileSystem`] for the
+/// real sysfs.
+///
+/// [`DuoFileSystem`]: crate::duo::DuoFileSystem
+pub fn open<F: FileSystemOps + 'static>(fs_ops: F) -> Result<Box<dyn Adc>> {
+    open_with::<MmioRegion, F>(AdcIio::new(0, fs_ops))
+}
+
+/// Like [`open`], probing `iio` and falling back to registers mapped
+/// through `R`.
+pub fn open_with<R: RegisterIo + 'static, F: FileSystemOps + 'static>(
+    iio: AdcIio<F>,
+) -> Result<Box<dyn Adc>> {
+    if iio.fs_ops.read_to_string(&iio.dir.join("in_voltage0_raw")).is_ok() {
+        Ok(Box::new(iio))
+    } else {
+        Ok(Box::new(AdcRegisters::<R>::new()?))
+    }
+}
@@ -0,0 +1,160 @@
+//! Ownership of GPIO lines.
+//!
+//! A line is claimed once per backend through an in-process registry, and
+//! on real hardware once per system through a lock file named after the
+//! line, so that two handles never drive or reset the same line. In-memory
+//! backends are independent of each other, see [`ClaimScope`]. The lock file
+//! holds the owner's PID for error messages; the claim itself is a `flock(2)`,
+//! which the kernel drops when the owner exits, so a crashed process never
+//! leaves a stale claim behind.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use std::{fmt, thread};
+
+use crate::port_lock::run_dir;
+use crate::{GpioError, Pin, Result};
+
+static CLAIMS: Mutex<Option<HashMap<(ClaimScope, Pin), PinOwner>>> = Mutex::new(None);
+
+/// Which handles a claim excludes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClaimScope {
+    /// Every handle on the real hardware, in this and other processes.
+    System,
+    /// Handles on one in-memory backend, identified by the address of its
+    /// shared storage.
+    Instance(usize),
+}
+
+/// Who holds a line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PinOwner {
+    /// Another handle in this process, created on the named thread.
+    Thread { name: String },
+    /// Another process, with its PID and command name when they could be
+    /// read. The lock file is empty for a moment while its owner stamps it.
+    Process { pid: Option<u32>, name: Option<String> },
+}
+
+impl fmt::Display for PinOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinOwner::Thread { name } => write!(f, "thread '{name}' of this process"),
+            PinOwner::Process { pid: Some(pid), name: Some(name) } => {
+                write!(f, "process {pid} ({name})")
+            },
+            PinOwner::Process { pid: Some(pid), name: None } => write!(f, "process {pid}"),
+            PinOwner::Process { pid: None, .. } => write!(f, "another process, PID unknown"),
+        }
+    }
+}
+
+/// An exclusive claim on a line, released on drop.
+#[derive(Debug)]
+pub struct PinClaim {
+    pin: Pin,
+    scope: ClaimScope,
+    file: Option<File>,
+}
+
+/// Path of the lock file of `pin`.
+pub fn claim_path(pin: Pin) -> PathBuf {
+    let name = format!("{:?}-{}.pid", pin.port(), pin.line()).to_lowercase();
+    run_dir().join("pins").join(name)
+}
+
+impl PinClaim {
+    /// Claims `pin` within `scope` for the calling thread's process. A
+    /// [`ClaimScope::System`] claim also excludes other processes.
+    ///
+    /// Fails with [`GpioError::PinClaimed`] naming the owner if the line is
+    /// already taken.
+    pub fn take(pin: Pin, scope: ClaimScope) -> Result<Self> {
+        let mut claims = CLAIMS.lock().unwrap_or_else(PoisonError::into_inner);
+        let claims = claims.get_or_insert_with(HashMap::new);
+        if let Some(owner) = claims.get(&(scope, pin)) {
+            return Err(GpioError::PinClaimed { pin, owner: owner.clone() });
+        }
+
+        let file = if scope == ClaimScope::System { Some(lock_pin(pin)?) } else { None };
+        let name = thread::current().name().unwrap_or("<unnamed>").to_string();
+        claims.insert((scope, pin), PinOwner::Thread { name });
+
+        Ok(Self { pin, scope, file })
+    }
+
+    /// Claims `pin` within the scope a backend asks for, or not at all for a
+    /// backend that opts out, see [`RegisterIo::claim_scope`].
+    ///
+    /// [`RegisterIo::claim_scope`]: crate::RegisterIo::claim_scope
+    pub fn take_for(pin: Pin, scope: Option<ClaimScope>) -> Result<Option<Self>> {
+        scope.map(|scope| Self::take(pin, scope)).transpose()
+    }
+
+    /// The current owner of `pin` within `scope` in this process, if any.
+    pub fn owner(pin: Pin, scope: ClaimScope) -> Option<PinOwner> {
+        let claims = CLAIMS.lock().unwrap_or_else(PoisonError::into_inner);
+        claims.as_ref()?.get(&(scope, pin)).cloned()
+    }
+
+    pub fn pin(&self) -> Pin {
+        self.pin
+    }
+}
+
+fn lock_pin(pin: Pin) -> Result<File> {
+    let path = claim_path(pin);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| GpioError::open(dir, e))?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| GpioError::open(&path, e))?;
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::WouldBlock {
+            return Err(GpioError::Lock { path, source: err });
+        }
+        let mut contents = String::new();
+        let _ = file.read_to_string(&mut contents);
+        let pid = contents.trim().parse().ok();
+        let name = pid
+            .and_then(|pid: u32| fs::read_to_string(format!("/proc/{pid}/comm")).ok())
+            .map(|comm| comm.trim().to_string());
+        return Err(GpioError::PinClaimed { pin, owner: PinOwner::Process { pid, name } });
+    }
+
+    let stamp = |file: &mut File| -> io::Result<()> {
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())
+    };
+    stamp(&mut file).map_err(|e| GpioError::Lock { path: path.clone(), source: e })?;
+
+    Ok(file)
+}
+
+impl Drop for PinClaim {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            // Empty the file before unlocking so nobody reads our stale PID.
+            if let Err(e) = file.set_len(0) {
+                log::error!("Error trying to clear claim on {:?}: {e}", self.pin);
+            }
+        }
+        let mut claims = CLAIMS.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(claims) = claims.as_mut() {
+            claims.remove(&(self.scope, self.pin));
+        }
+    }
+}
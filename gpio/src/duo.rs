@@ -1,21 +1,61 @@
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
-
-use anyhow::{anyhow, Result};
-
-use crate::gpio_mmap::DevMem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::claim::PinClaim;
+use crate::gpio_mmap::{DevMem, MmioRegion};
+use crate::pinmux::{Pad, PadConfig, PadControl};
+use crate::port_lock::{self, PortGuard};
 use crate::GpioDirection::GpioInput;
-use crate::{FileSystemOps, Gpio, GpioDirection, GpioPort, IntLevelType, IntPolarity};
+use crate::{
+    DebounceClock, Device, DropPolicy, FileSystemOps, Gpio, GpioDirection, GpioError, GpioPort,
+    IntLevelType, IntPolarity, Pin, RegisterIo, Result, Trigger,
+};
 
 pub struct DuoFileSystem;
 
 impl FileSystemOps for DuoFileSystem {
+    const CROSS_PROCESS: bool = true;
+
     fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
-        Ok(fs::write(path, content)?)
+        fs::write(path, content).map_err(|e| GpioError::sysfs(path, e))
     }
 
     fn read_to_string(&self, path: &Path) -> Result<String> {
-        Ok(fs::read_to_string(path)?)
+        fs::read_to_string(path).map_err(|e| GpioError::sysfs(path, e))
+    }
+
+    fn poll_priority(&self, path: &Path, timeout: Option<Duration>) -> Result<bool> {
+        let mut file = File::open(path).map_err(|e| GpioError::sysfs(path, e))?;
+
+        // sysfs only reports changes that happen after the attribute was read.
+        let mut value = String::new();
+        file.read_to_string(&mut value).map_err(|e| GpioError::sysfs(path, e))?;
+
+        let mut poll_fd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLPRI | libc::POLLERR,
+            revents: 0,
+        };
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+        loop {
+            let result = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+
+            if result >= 0 {
+                return Ok(result > 0);
+            }
+
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(GpioError::sysfs(path, err));
+            }
+        }
     }
 }
 
@@ -26,7 +66,29 @@ pub const GPIO2_BASE: usize = GPIO_BASE_ADDRESS + 0x2000;
 pub const GPIO3_BASE: usize = GPIO_BASE_ADDRESS + 0x3000;
 pub const PWR_GPIO_BASE: usize = 0x05021000;
 
+/// Clock generator, which divides the debounce clock off the 25 MHz crystal.
+pub const CLKGEN_BASE: usize = 0x03002000;
+const CLKGEN_LEN: usize = 0x100;
+pub const DIV_CLK_GPIO_DB: usize = CLKGEN_BASE + 0x094;
+const DB_CLK_SOURCE_HZ: u32 = 25_000_000;
+/// Take the divider from the register rather than the reset default.
+const DIV_SELECT_REG: u32 = 1 << 3;
+/// Divider in effect while [`DIV_SELECT_REG`] is clear, giving 100 kHz.
+const DB_CLK_DEFAULT_DIVIDER: u32 = 250;
+const DIV_FACTOR_SHIFT: u32 = 16;
+const DIV_FACTOR_MASK: u32 = 0xffff << DIV_FACTOR_SHIFT;
+
+/// Lines bonded out on the CV1800B, one bit per line.
+const XGPIOA_LINES: u32 = 0x7fcf_ff80; // 7..=19, 22..=30
+const XGPIOB_LINES: u32 = 0x0f00_0048; // 3, 6, 24..=27
+const XGPIOC_LINES: u32 = 0x0103_fffc; // 2..=17, 24
+const XGPIOD_LINES: u32 = 0;
+const PWR_GPIO_LINES: u32 = 0x06fc_015f; // 0..=4, 6, 8, 18..=23, 25, 26
+
 impl GpioPort {
+    pub const ALL: [GpioPort; 5] =
+        [GpioPort::Port0, GpioPort::Port1, GpioPort::Port2, GpioPort::Port3, GpioPort::Pwr];
+
     pub fn base_address(&self) -> usize {
         match self {
             GpioPort::Port0 => GPIO0_BASE,
@@ -36,6 +98,161 @@ impl GpioPort {
             GpioPort::Pwr => PWR_GPIO_BASE,
         }
     }
+
+    /// Bitmask of the lines that exist on this port.
+    pub fn valid_lines(&self) -> u32 {
+        match self {
+            GpioPort::Port0 => XGPIOA_LINES,
+            GpioPort::Port1 => XGPIOB_LINES,
+            GpioPort::Port2 => XGPIOC_LINES,
+            GpioPort::Port3 => XGPIOD_LINES,
+            GpioPort::Pwr => PWR_GPIO_LINES,
+        }
+    }
+
+    /// First global sysfs GPIO number of this port's gpiochip.
+    pub fn sysfs_base(&self) -> u32 {
+        match self {
+            GpioPort::Port0 => 480,
+            GpioPort::Port1 => 448,
+            GpioPort::Port2 => 416,
+            GpioPort::Port3 => 384,
+            GpioPort::Pwr => 352,
+        }
+    }
+
+    /// Position in [`GpioPort::ALL`], which is also the gpiochip index.
+    pub fn index(&self) -> usize {
+        match self {
+            GpioPort::Port0 => 0,
+            GpioPort::Port1 => 1,
+            GpioPort::Port2 => 2,
+            GpioPort::Port3 => 3,
+            GpioPort::Pwr => 4,
+        }
+    }
+}
+
+/// Header pins of the Milk-V Duo, from the board pinout. The Duo 256M keeps
+/// the same header.
+const DUO_HEADER: &[(&str, GpioPort, u32)] = &[
+    ("GP0", GpioPort::Port0, 28),
+    ("GP1", GpioPort::Port0, 29),
+    ("GP2", GpioPort::Pwr, 26),
+    ("GP3", GpioPort::Pwr, 25),
+    ("GP4", GpioPort::Pwr, 19),
+    ("GP5", GpioPort::Pwr, 20),
+    ("GP6", GpioPort::Pwr, 23),
+    ("GP7", GpioPort::Pwr, 22),
+    ("GP8", GpioPort::Pwr, 21),
+    ("GP9", GpioPort::Pwr, 18),
+    ("GP10", GpioPort::Port2, 9),
+    ("GP11", GpioPort::Port2, 10),
+    ("GP12", GpioPort::Port0, 16),
+    ("GP13", GpioPort::Port0, 17),
+    ("GP14", GpioPort::Port0, 14),
+    ("GP15", GpioPort::Port0, 15),
+    ("GP16", GpioPort::Port0, 23),
+    ("GP17", GpioPort::Port0, 24),
+    ("GP18", GpioPort::Port0, 22),
+    ("GP19", GpioPort::Port0, 25),
+    ("GP20", GpioPort::Port0, 27),
+    ("GP21", GpioPort::Port0, 26),
+    ("GP22", GpioPort::Pwr, 4),
+    ("GP26", GpioPort::Port1, 3),
+    ("GP27", GpioPort::Port1, 6),
+];
+
+/// GPIO pins of the Duo S's 26-pin J3 header, which is labelled with short
+/// SoC names: `A16` is XGPIOA[16], `B20` is XGPIOB[20].
+const DUOS_HEADER: &[(&str, GpioPort, u32)] = &[
+    ("B20", GpioPort::Port1, 20),
+    ("B21", GpioPort::Port1, 21),
+    ("B18", GpioPort::Port1, 18),
+    ("A16", GpioPort::Port0, 16),
+    ("A17", GpioPort::Port0, 17),
+    ("B11", GpioPort::Port1, 11),
+    ("B19", GpioPort::Port1, 19),
+    ("B12", GpioPort::Port1, 12),
+    ("B22", GpioPort::Port1, 22),
+    ("A20", GpioPort::Port0, 20),
+    ("A19", GpioPort::Port0, 19),
+    ("B13", GpioPort::Port1, 13),
+    ("B14", GpioPort::Port1, 14),
+    ("A18", GpioPort::Port0, 18),
+    ("B15", GpioPort::Port1, 15),
+    ("B16", GpioPort::Port1, 16),
+    ("A28", GpioPort::Port0, 28),
+];
+
+impl Device {
+    /// Header names and the SoC lines they are wired to.
+    pub fn header(&self) -> &'static [(&'static str, GpioPort, u32)] {
+        match self {
+            Device::Duo | Device::Duo256M => DUO_HEADER,
+            Device::DuoS => DUOS_HEADER,
+        }
+    }
+
+    /// Bitmask of the lines of `port` that exist on this board's SoC. The
+    /// Duo S's SG2000 also brings out lines that the CV1800B does not bond,
+    /// so its header lines are added to the CV1800B tables.
+    pub fn valid_lines(&self, port: GpioPort) -> u32 {
+        match self {
+            Device::Duo | Device::Duo256M => port.valid_lines(),
+            Device::DuoS => DUOS_HEADER
+                .iter()
+                .filter(|(_, p, _)| *p == port)
+                .fold(port.valid_lines(), |lines, (_, _, line)| lines | 1 << line),
+        }
+    }
+
+    /// The on-board LED.
+    pub fn led(&self) -> Pin {
+        let (port, line) = match self {
+            Device::Duo => (GpioPort::Port2, 24),
+            Device::Duo256M => (GpioPort::Pwr, 2),
+            Device::DuoS => (GpioPort::Port0, 29),
+        };
+        Pin::on(*self, port, line).expect("LED is wired to a valid line")
+    }
+
+    /// Resolves a header name such as `"GP4"`, `"B20"` on the Duo S, or
+    /// `"LED"`, or a SoC name such as `"XGPIOC[24]"` or `"PWR_GPIO[4]"`.
+    /// Names are case-insensitive.
+    pub fn pin(&self, name: &str) -> Result<Pin> {
+        let unknown = || GpioError::UnknownPinName { device: *self, name: name.to_string() };
+        let upper = name.trim().to_ascii_uppercase();
+
+        // The LED is also exposed as GP25 on the boards with GP names, like
+        // on the Raspberry Pi Pico.
+        if upper == "LED" || (upper == "GP25" && *self != Device::DuoS) {
+            return Ok(self.led());
+        }
+        if let Some((_, port, line)) = self.header().iter().find(|(pin, ..)| *pin == upper) {
+            return Pin::on(*self, *port, *line);
+        }
+
+        let (port, line) =
+            upper.strip_suffix(']').and_then(|name| name.split_once('[')).ok_or_else(unknown)?;
+        let port = match port {
+            "XGPIOA" => GpioPort::Port0,
+            "XGPIOB" => GpioPort::Port1,
+            "XGPIOC" => GpioPort::Port2,
+            "XGPIOD" => GpioPort::Port3,
+            "PWR_GPIO" => GpioPort::Pwr,
+            _ => return Err(unknown()),
+        };
+        let line = line.parse().map_err(|_| unknown())?;
+
+        Pin::on(*self, port, line)
+    }
+
+    /// Resolves `name` like [`Device::pin`] and returns the pad carrying it.
+    pub fn pad(&self, name: &str) -> Result<Pad> {
+        let pin = self.pin(name)?;
+        Pad::for_pin(pin).ok_or(GpioError::NoPadForPin { pin })
+    }
 }
 
 #[derive(Debug)]
@@ -55,25 +272,39 @@ pub struct DuoGpio {
     ext_porta: u32,
     __reserved2: [u32; 3],
     ls_sync: u32,
+    __reserved3: u32,
+    /// Only present on newer configurations of the block, see
+    /// [`MilkVDuoGpio::has_bothedge`].
+    int_bothedge: u32,
 }
 
 impl DuoGpio {
     pub fn new(base: usize) -> Result<&'static Self> {
         if base == 0 {
-            return Err(anyhow!("Base cannot be NULL"));
+            return Err(GpioError::InvalidBase { base });
         }
 
         let gpio_base = base as *const DuoGpio;
 
         unsafe {
             if gpio_base.is_null() {
-                Err(anyhow!("Base is NULL"))
+                Err(GpioError::InvalidBase { base })
             } else {
                 Ok(&*gpio_base)
             }
         }
     }
 
+    /// Maps the whole register block once so it can be accessed without a
+    /// mmap per register access.
+    pub fn map(&self, dev: &DevMem) -> Result<MmioRegion> {
+        dev.map_region(self.base(), size_of::<DuoGpio>())
+    }
+
+    pub fn base(&self) -> usize {
+        self as *const _ as _
+    }
+
     pub fn swporta_dr(&self) -> usize {
         &self.swporta_dr as *const _ as _
     }
@@ -121,50 +352,315 @@ impl DuoGpio {
     pub fn ls_sync(&self) -> usize {
         &self.ls_sync as *const _ as _
     }
+
+    pub fn int_bothedge(&self) -> usize {
+        &self.int_bothedge as *const _ as _
+    }
+
+    /// Name of the register at `addr`, for error reporting.
+    pub fn register_name(&self, addr: usize) -> &'static str {
+        match addr {
+            DIV_CLK_GPIO_DB => "div_clk_gpio_db",
+            a if a == self.swporta_dr() => "swporta_dr",
+            a if a == self.swporta_ddr() => "swporta_ddr",
+            a if a == self.inten() => "inten",
+            a if a == self.intmask() => "intmask",
+            a if a == self.inttype_level() => "inttype_level",
+            a if a == self.int_polarity() => "int_polarity",
+            a if a == self.intstatus() => "intstatus",
+            a if a == self.raw_intstatus() => "raw_intstatus",
+            a if a == self.debounce() => "debounce",
+            a if a == self.porta_eoi() => "porta_eoi",
+            a if a == self.ext_porta() => "ext_porta",
+            a if a == self.ls_sync() => "ls_sync",
+            a if a == self.int_bothedge() => "int_bothedge",
+            _ => "unknown",
+        }
+    }
+}
+
+/// A line's bit in each of the port's configuration registers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PinSnapshot {
+    pub dr: bool,
+    pub ddr: bool,
+    pub inten: bool,
+    pub intmask: bool,
+    pub inttype_level: bool,
+    pub int_polarity: bool,
+    pub debounce: bool,
+}
+
+impl PinSnapshot {
+    /// Registers paired with their bit, in the order they are restored: the
+    /// output level before the direction, and the trigger setup before the
+    /// interrupt is unmasked and enabled.
+    fn fields(&self, duo: &DuoGpio) -> [(usize, bool); 7] {
+        [
+            (duo.swporta_dr(), self.dr),
+            (duo.inttype_level(), self.inttype_level),
+            (duo.int_polarity(), self.int_polarity),
+            (duo.debounce(), self.debounce),
+            (duo.intmask(), self.intmask),
+            (duo.inten(), self.inten),
+            (duo.swporta_ddr(), self.ddr),
+        ]
+    }
 }
 
-pub struct MilkVDuoGpio<'a> {
+pub struct MilkVDuoGpio<'a, R: RegisterIo = MmioRegion> {
+    port: GpioPort,
     pin: u32,
     bitmask: u32,
     duo: &'a DuoGpio,
-    dev: DevMem,
+    regs: R,
+    drop_policy: DropPolicy,
+    snapshot: PinSnapshot,
+    bothedge: OnceLock<bool>,
+    emulate_both: AtomicBool,
+    clkgen: OnceLock<R>,
+    _claim: Option<PinClaim>,
 }
 
-impl Gpio for MilkVDuoGpio<'_> {
+impl<R: RegisterIo> MilkVDuoGpio<'_, R> {
+    /// Creates a handle that accesses the port registers through `regs`.
+    pub fn with_registers(port: GpioPort, pin: u32, regs: R) -> Result<Self> {
+        Self::from_pin(Pin::new(port, pin)?, regs)
+    }
+
+    /// Fails with [`GpioError::PinClaimed`] if another handle already owns
+    /// the line; on real registers that includes other processes. The line's
+    /// register bits are captured for [`DropPolicy::Restore`].
+    pub fn from_pin(pin: Pin, regs: R) -> Result<Self> {
+        let duo = DuoGpio::new(pin.port().base_address())?;
+        let claim = PinClaim::take_for(pin, regs.claim_scope())?;
+        let mut gpio = Self {
+            port: pin.port(),
+            pin: pin.line(),
+            bitmask: pin.bitmask(),
+            duo,
+            regs,
+            // Nothing has been changed yet, so a failed capture must not
+            // reset the line on the way out.
+            drop_policy: DropPolicy::LeaveAsIs,
+            snapshot: PinSnapshot::default(),
+            bothedge: OnceLock::new(),
+            emulate_both: AtomicBool::new(false),
+            clkgen: OnceLock::new(),
+            _claim: claim,
+        };
+        gpio.snapshot = gpio.capture()?;
+        gpio.drop_policy = DropPolicy::default();
+
+        Ok(gpio)
+    }
+
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Accesses the clock controller through `regs` instead of mapping it
+    /// on first use.
+    pub fn with_clock_registers(self, regs: R) -> Self {
+        let _ = self.clkgen.set(regs);
+        self
+    }
+
+    fn clkgen(&self) -> Result<&R> {
+        if let Some(regs) = self.clkgen.get() {
+            return Ok(regs);
+        }
+        let regs = R::map(CLKGEN_BASE, CLKGEN_LEN)?;
+        Ok(self.clkgen.get_or_init(|| regs))
+    }
+
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.drop_policy = policy;
+    }
+
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    /// The line's register bits when the handle was opened.
+    pub fn snapshot(&self) -> PinSnapshot {
+        self.snapshot
+    }
+
+    /// Reads the line's current register bits.
+    pub fn capture(&self) -> Result<PinSnapshot> {
+        let bit = |addr| Ok::<_, GpioError>(self.read_reg(addr)? & self.bitmask != 0);
+        Ok(PinSnapshot {
+            dr: bit(self.duo.swporta_dr())?,
+            ddr: bit(self.duo.swporta_ddr())?,
+            inten: bit(self.duo.inten())?,
+            intmask: bit(self.duo.intmask())?,
+            inttype_level: bit(self.duo.inttype_level())?,
+            int_polarity: bit(self.duo.int_polarity())?,
+            debounce: bit(self.duo.debounce())?,
+        })
+    }
+
+    /// Writes back `snapshot`, touching only this line's bit in each
+    /// register.
+    pub fn restore(&self, snapshot: &PinSnapshot) -> Result<()> {
+        let _lock = self.lock()?;
+        for (addr, set) in snapshot.fields(self.duo) {
+            let val = self.read_reg(addr)?;
+            let new = if set { val | self.bitmask } else { val & !self.bitmask };
+            if new != val {
+                self.write_reg(addr, new)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &R {
+        &self.regs
+    }
+
+    pub fn pin(&self) -> Pin {
+        Pin { port: self.port, line: self.pin }
+    }
+
+    /// Like [`Gpio::init`], but applies `config` to the pad first so that
+    /// e.g. the pull-up is in place before the line is read.
+    pub fn init_with_pad<P: RegisterIo>(
+        &self,
+        pin_direction: GpioDirection,
+        pads: &PadControl<P>,
+        config: &PadConfig,
+    ) -> Result<()> {
+        pads.set_pin_config(self.pin(), config)?;
+        self.init(pin_direction)
+    }
+
+    /// Whether the block has the `int_bothedge` register, probed once by
+    /// setting this line's bit and reading it back.
+    pub fn has_bothedge(&self) -> Result<bool> {
+        if let Some(present) = self.bothedge.get() {
+            return Ok(*present);
+        }
+
+        let int_bothedge = self.duo.int_bothedge();
+        let present = {
+            let _lock = self.lock()?;
+            let val = self.read_reg(int_bothedge)?;
+            self.write_reg(int_bothedge, val | self.bitmask)?;
+            let probed = self.read_reg(int_bothedge)?;
+            self.write_reg(int_bothedge, val)?;
+            probed & self.bitmask != 0
+        };
+        log::debug!("{:?} int_bothedge present: {present}", self.port);
+
+        Ok(*self.bothedge.get_or_init(|| present))
+    }
+
+    /// Points the edge polarity at the opposite of the current pad level, so
+    /// the next change in either direction latches an interrupt.
+    ///
+    /// An edge that lands between reading the level and switching polarity
+    /// is not latched; the level is read again afterwards so the polarity at
+    /// least ends up facing the right way.
+    fn follow_level(&self) -> Result<()> {
+        let mut level = self.read_pin()?;
+        loop {
+            let polarity =
+                if level != 0 { IntPolarity::ActiveLow } else { IntPolarity::ActiveHigh };
+            self.set_interrupt_polarity(polarity)?;
+            let now = self.read_pin()?;
+            if now == level {
+                return Ok(());
+            }
+            level = now;
+        }
+    }
+
+    /// Locks the port for a read-modify-write cycle.
+    fn lock(&self) -> Result<PortGuard> {
+        port_lock::lock_port(self.port, R::CROSS_PROCESS)
+    }
+
+    fn read_reg(&self, addr: usize) -> Result<u32> {
+        self.regs.read32(addr).map_err(|e| self.register_error(addr, e))
+    }
+
+    fn write_reg(&self, addr: usize, val: u32) -> Result<()> {
+        self.regs.write32(addr, val).map_err(|e| self.register_error(addr, e))
+    }
+
+    fn read_clkgen(&self) -> Result<u32> {
+        self.clkgen()
+            .and_then(|clkgen| clkgen.read32(DIV_CLK_GPIO_DB))
+            .map_err(|e| self.register_error(DIV_CLK_GPIO_DB, e))
+    }
+
+    fn write_clkgen(&self, val: u32) -> Result<()> {
+        self.clkgen()
+            .and_then(|clkgen| clkgen.write32(DIV_CLK_GPIO_DB, val))
+            .map_err(|e| self.register_error(DIV_CLK_GPIO_DB, e))
+    }
+
+    fn register_error(&self, addr: usize, source: GpioError) -> GpioError {
+        GpioError::Register {
+            port: self.port,
+            pin: self.pin,
+            register: self.duo.register_name(addr),
+            addr,
+            source: Box::new(source),
+        }
+    }
+}
+
+impl<R: RegisterIo> Gpio for MilkVDuoGpio<'_, R> {
     fn new(port: GpioPort, pin: u32) -> Result<Self> {
-        let bitmask = 1 << pin;
-        let duo = DuoGpio::new(port.base_address())?;
-        let dev = DevMem::new()?;
-        Ok(Self { pin, bitmask, duo, dev })
+        let pin = Pin::new(port, pin)?;
+        let regs = R::map(port.base_address(), size_of::<DuoGpio>())?;
+        Self::from_pin(pin, regs)
     }
 
     fn init(&self, pin_direction: GpioDirection) -> Result<()> {
+        let _lock = self.lock()?;
         let swporta_ddr = self.duo.swporta_ddr();
-        let mut swporta_ddr_val = self.dev.mem_read(swporta_ddr)?;
+        let mut swporta_ddr_val = self.read_reg(swporta_ddr)?;
 
         match pin_direction {
             GpioDirection::GpioInput => swporta_ddr_val &= !self.bitmask,
             GpioDirection::GpioOutput => swporta_ddr_val |= self.bitmask,
         };
 
-        self.dev.mem_write(swporta_ddr, swporta_ddr_val)
+        self.write_reg(swporta_ddr, swporta_ddr_val)
     }
 
     fn write_pin(&self, pin_state: bool) -> Result<()> {
+        let _lock = self.lock()?;
         let swporta_dr = self.duo.swporta_dr();
-        let mut swporta_dr_val = self.dev.mem_read(swporta_dr)?;
+        let mut swporta_dr_val = self.read_reg(swporta_dr)?;
 
         match pin_state {
             false => swporta_dr_val &= !self.bitmask,
             true => swporta_dr_val |= self.bitmask,
         };
 
-        self.dev.mem_write(swporta_dr, swporta_dr_val)
+        self.write_reg(swporta_dr, swporta_dr_val)
     }
 
     fn read_pin(&self) -> Result<u32> {
+        let ext_porta = self.duo.ext_porta();
+        let ext_porta_val = self.read_reg(ext_porta)? & self.bitmask;
+
+        if ext_porta_val > 0 {
+            Ok(1)
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn read_output_latch(&self) -> Result<u32> {
         let swporta_dr = self.duo.swporta_dr();
-        let swporta_dr_val = self.dev.mem_read(swporta_dr)? & self.bitmask;
+        let swporta_dr_val = self.read_reg(swporta_dr)? & self.bitmask;
 
         if swporta_dr_val > 0 {
             Ok(1)
@@ -173,6 +669,17 @@ impl Gpio for MilkVDuoGpio<'_> {
         }
     }
 
+    fn direction(&self) -> Result<GpioDirection> {
+        let swporta_ddr = self.duo.swporta_ddr();
+        let swporta_ddr_val = self.read_reg(swporta_ddr)? & self.bitmask;
+
+        if swporta_ddr_val > 0 {
+            Ok(GpioDirection::GpioOutput)
+        } else {
+            Ok(GpioDirection::GpioInput)
+        }
+    }
+
     fn enable_interrupt(&self) -> Result<()> {
         let inten = self.duo.inten();
         self.enable(inten)
@@ -203,49 +710,233 @@ impl Gpio for MilkVDuoGpio<'_> {
         self.disable(debounce)
     }
 
+    fn debounce_clock(&self) -> Result<DebounceClock> {
+        let val = self.read_clkgen()?;
+        let divider = if val & DIV_SELECT_REG != 0 {
+            (val & DIV_FACTOR_MASK) >> DIV_FACTOR_SHIFT
+        } else {
+            DB_CLK_DEFAULT_DIVIDER
+        };
+
+        Ok(DebounceClock { source_hz: DB_CLK_SOURCE_HZ, divider: divider.max(1) })
+    }
+
+    fn set_debounce_clock(&self, hz: u32) -> Result<DebounceClock> {
+        if hz == 0 || hz > DB_CLK_SOURCE_HZ {
+            return Err(GpioError::InvalidDebounceClock { hz });
+        }
+        let divider = ((DB_CLK_SOURCE_HZ + hz / 2) / hz).clamp(1, 0xffff);
+
+        let _lock = port_lock::lock_clkgen(R::CROSS_PROCESS)?;
+        let val = self.read_clkgen()?;
+        let val = (val & !DIV_FACTOR_MASK) | (divider << DIV_FACTOR_SHIFT) | DIV_SELECT_REG;
+        self.write_clkgen(val)?;
+
+        Ok(DebounceClock { source_hz: DB_CLK_SOURCE_HZ, divider })
+    }
+
+    fn set_level_sync(&self, enable: bool) -> Result<()> {
+        let ls_sync = self.duo.ls_sync();
+        self.write_reg(ls_sync, enable as u32)
+    }
+
+    fn level_sync(&self) -> Result<bool> {
+        let ls_sync = self.duo.ls_sync();
+        Ok(self.read_reg(ls_sync)? & 1 != 0)
+    }
+
     fn set_interrupt_level_type(&self, level_type: IntLevelType) -> Result<()> {
+        let _lock = self.lock()?;
         let inttype_level = self.duo.inttype_level();
-        let mut inttype_level_val = self.dev.mem_read(inttype_level)?;
+        let mut inttype_level_val = self.read_reg(inttype_level)?;
 
         match level_type {
             IntLevelType::LevelSensitive => inttype_level_val &= !self.bitmask,
             IntLevelType::EdgeSensitive => inttype_level_val |= self.bitmask,
         }
 
-        self.dev.mem_write(inttype_level, inttype_level_val)
+        self.write_reg(inttype_level, inttype_level_val)
     }
 
     fn set_interrupt_polarity(&self, polarity: IntPolarity) -> Result<()> {
+        let _lock = self.lock()?;
         let int_polarity = self.duo.int_polarity();
-        let mut int_polarity_val = self.dev.mem_read(int_polarity)?;
+        let mut int_polarity_val = self.read_reg(int_polarity)?;
 
         match polarity {
             IntPolarity::ActiveLow => int_polarity_val &= !self.bitmask,
             IntPolarity::ActiveHigh => int_polarity_val |= self.bitmask,
         };
 
-        self.dev.mem_write(int_polarity, int_polarity_val)
+        self.write_reg(int_polarity, int_polarity_val)
+    }
+
+    fn interrupt_pending(&self) -> Result<bool> {
+        let intstatus = self.duo.intstatus();
+        Ok(self.read_reg(intstatus)? & self.bitmask != 0)
+    }
+
+    fn raw_interrupt_pending(&self) -> Result<bool> {
+        let raw_intstatus = self.duo.raw_intstatus();
+        Ok(self.read_reg(raw_intstatus)? & self.bitmask != 0)
+    }
+
+    fn clear_interrupt(&self) -> Result<()> {
+        // porta_eoi is write-one-to-clear, other lines are unaffected.
+        let porta_eoi = self.duo.porta_eoi();
+        self.write_reg(porta_eoi, self.bitmask)?;
+
+        if self.emulate_both.load(Ordering::Relaxed) {
+            self.follow_level()?;
+        }
+
+        Ok(())
+    }
+
+    /// [`Trigger::Both`] uses `int_bothedge` when the block has it, and
+    /// otherwise flips the polarity on every [`clear_interrupt`].
+    ///
+    /// [`clear_interrupt`]: Gpio::clear_interrupt
+    fn configure_interrupt(&self, trigger: Trigger) -> Result<()> {
+        self.enable_interrupt_mask()?;
+
+        let both = trigger == Trigger::Both;
+        let hardware_both = self.has_bothedge()?;
+        if hardware_both {
+            match both {
+                true => self.enable(self.duo.int_bothedge())?,
+                false => self.disable(self.duo.int_bothedge())?,
+            }
+        }
+        let emulate = both && !hardware_both;
+        self.emulate_both.store(emulate, Ordering::Relaxed);
+
+        self.set_interrupt_level_type(trigger.level_type())?;
+        match emulate {
+            true => self.follow_level()?,
+            false => self.set_interrupt_polarity(trigger.polarity())?,
+        }
+        self.enable_interrupt()?;
+        self.clear_interrupt()?;
+        self.disable_interrupt_mask()
     }
 
     fn enable(&self, addr: usize) -> Result<()> {
-        let mut val = self.dev.mem_read(addr)?;
+        let _lock = self.lock()?;
+        let mut val = self.read_reg(addr)?;
         val |= self.bitmask;
 
-        self.dev.mem_write(addr, val)
+        self.write_reg(addr, val)
     }
 
     fn disable(&self, addr: usize) -> Result<()> {
-        let mut val = self.dev.mem_read(addr)?;
+        let _lock = self.lock()?;
+        let mut val = self.read_reg(addr)?;
         val &= !self.bitmask;
 
-        self.dev.mem_write(addr, val)
+        self.write_reg(addr, val)
     }
 }
 
-impl Drop for MilkVDuoGpio<'_> {
+impl<R: RegisterIo> Drop for MilkVDuoGpio<'_, R> {
     fn drop(&mut self) {
-        if let Err(e) = self.init(GpioInput) {
+        let result = match self.drop_policy {
+            DropPolicy::LeaveAsIs => Ok(()),
+            DropPolicy::ResetToInput => self.init(GpioInput),
+            DropPolicy::Restore => self.restore(&self.snapshot),
+        };
+        if let Err(e) = result {
             log::error!("Error: {e}, unable to reset pin: {}", self.pin)
         }
     }
 }
+
+/// All lines of one port, updated together.
+///
+/// Every masked operation is a single read-modify-write of one register, so
+/// the lines in `mask` change in the same bus write and the other lines are
+/// left alone.
+pub struct GpioPortHandle<'a, R: RegisterIo = MmioRegion> {
+    port: GpioPort,
+    duo: &'a DuoGpio,
+    regs: R,
+}
+
+impl<R: RegisterIo> GpioPortHandle<'_, R> {
+    pub fn new(port: GpioPort) -> Result<Self> {
+        let regs = R::map(port.base_address(), size_of::<DuoGpio>())?;
+        Self::with_registers(port, regs)
+    }
+
+    pub fn with_registers(port: GpioPort, regs: R) -> Result<Self> {
+        let duo = DuoGpio::new(port.base_address())?;
+        Ok(Self { port, duo, regs })
+    }
+
+    pub fn port(&self) -> GpioPort {
+        self.port
+    }
+
+    pub fn registers(&self) -> &R {
+        &self.regs
+    }
+
+    /// Drives the lines in `mask` to the matching bits of `value`.
+    pub fn write_masked(&self, mask: u32, value: u32) -> Result<()> {
+        self.update(self.duo.swporta_dr(), mask, |dr| (dr & !mask) | (value & mask))
+    }
+
+    /// Sets the lines in `mask` to output where `dir_bits` is 1 and to input
+    /// where it is 0.
+    pub fn set_direction_masked(&self, mask: u32, dir_bits: u32) -> Result<()> {
+        self.update(self.duo.swporta_ddr(), mask, |ddr| (ddr & !mask) | (dir_bits & mask))
+    }
+
+    pub fn toggle_masked(&self, mask: u32) -> Result<()> {
+        self.update(self.duo.swporta_dr(), mask, |dr| dr ^ mask)
+    }
+
+    /// Pad levels of every line on the port. Bits of lines that are not
+    /// bonded out read as 0.
+    pub fn read_all(&self) -> Result<u32> {
+        Ok(self.read_reg(self.duo.ext_porta())? & self.port.valid_lines())
+    }
+
+    /// Output latch of every line on the port.
+    pub fn read_output_latch(&self) -> Result<u32> {
+        Ok(self.read_reg(self.duo.swporta_dr())? & self.port.valid_lines())
+    }
+
+    /// Direction bits of every line on the port, 1 for output.
+    pub fn direction(&self) -> Result<u32> {
+        Ok(self.read_reg(self.duo.swporta_ddr())? & self.port.valid_lines())
+    }
+
+    fn update(&self, addr: usize, mask: u32, f: impl FnOnce(u32) -> u32) -> Result<()> {
+        let invalid = mask & !self.port.valid_lines();
+        if invalid != 0 {
+            return Err(GpioError::InvalidPin { port: self.port, pin: invalid.trailing_zeros() });
+        }
+
+        let _lock = port_lock::lock_port(self.port, R::CROSS_PROCESS)?;
+        let val = self.read_reg(addr)?;
+        self.write_reg(addr, f(val))
+    }
+
+    fn read_reg(&self, addr: usize) -> Result<u32> {
+        self.regs.read32(addr).map_err(|e| self.register_error(addr, e))
+    }
+
+    fn write_reg(&self, addr: usize, val: u32) -> Result<()> {
+        self.regs.write32(addr, val).map_err(|e| self.register_error(addr, e))
+    }
+
+    fn register_error(&self, addr: usize, source: GpioError) -> GpioError {
+        GpioError::PortRegister {
+            port: self.port,
+            register: self.duo.register_name(addr),
+            addr,
+            source: Box::new(source),
+        }
+    }
+}
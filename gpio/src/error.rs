@@ -0,0 +1,176 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::claim::PinOwner;
+use crate::{Device, GpioPort, Pin, Trigger};
+
+pub type Result<T> = std::result::Result<T, GpioError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GpioError {
+    /// The device or sysfs node exists but we are not allowed to open it,
+    /// e.g. `/dev/mem` without `CAP_SYS_RAWIO`.
+    #[error("Permission denied opening {}", path.display())]
+    PermissionDenied { path: PathBuf, source: io::Error },
+
+    #[error("Error opening {}", path.display())]
+    Open { path: PathBuf, source: io::Error },
+
+    #[error("Unable to map {len} bytes at address {addr:#010x}")]
+    Mmap { addr: usize, len: usize, source: io::Error },
+
+    #[error("Error running munmap at address {addr:#010x}")]
+    Munmap { addr: usize, source: io::Error },
+
+    #[error("Address {addr:#010x} is outside the mapped region {base:#010x}..{:#010x}", base + len)]
+    OutOfRange { addr: usize, base: usize, len: usize },
+
+    #[error("Register address {addr:#010x} is not 32-bit aligned")]
+    Unaligned { addr: usize },
+
+    #[error("Base address {base:#010x} is invalid")]
+    InvalidBase { base: usize },
+
+    /// A register access failed while driving a pin.
+    #[error("Failed to access {register} ({addr:#010x}) for {port:?} pin {pin}")]
+    Register {
+        port: GpioPort,
+        pin: u32,
+        register: &'static str,
+        addr: usize,
+        source: Box<GpioError>,
+    },
+
+    /// A register access failed while driving a whole port.
+    #[error("Failed to access {register} ({addr:#010x}) for {port:?}")]
+    PortRegister { port: GpioPort, register: &'static str, addr: usize, source: Box<GpioError> },
+
+    #[error("Invalid pin {pin} on {port:?}")]
+    InvalidPin { port: GpioPort, pin: u32 },
+
+    #[error("No pin named {name:?} on {device:?}")]
+    UnknownPinName { device: Device, name: String },
+
+    #[error("GPIO {number} does not belong to any GPIO controller")]
+    UnknownSysfsGpio { number: u32 },
+
+    #[error("GPIO {pin} is already exported")]
+    AlreadyExported { pin: u32, source: io::Error },
+
+    #[error("Error accessing {}", path.display())]
+    Sysfs { path: PathBuf, source: io::Error },
+
+    #[error("{pin:?} is already claimed by {owner}")]
+    PinClaimed { pin: Pin, owner: PinOwner },
+
+    #[error("Unable to lock {}", path.display())]
+    Lock { path: PathBuf, source: io::Error },
+
+    #[error("{ioctl} failed")]
+    Ioctl { ioctl: &'static str, source: io::Error },
+
+    /// The fd could not be registered with, or polled by, the async runtime.
+    #[error("Async runtime error on fd {fd}")]
+    Reactor { fd: i32, source: io::Error },
+
+    #[error("A line request needs 1 to 64 lines, got {count}")]
+    InvalidLineCount { count: usize },
+
+    #[error("There is no PWM{channel}")]
+    InvalidPwmChannel { channel: u32 },
+
+    #[error("PWM period {period:?} is out of range")]
+    InvalidPwmPeriod { period: Duration },
+
+    #[error("PWM duty cycle {duty:?} is longer than the period {period:?}")]
+    InvalidPwmDuty { duty: Duration, period: Duration },
+
+    #[error("{address:#x} is not a valid I2C address")]
+    InvalidI2cAddress { address: u16 },
+
+    #[error(
+        "I2C transfer of {messages} messages, the longest {len} bytes, exceeds the kernel limits"
+    )]
+    InvalidI2cTransfer { messages: usize, len: usize },
+
+    #[error("SPI transfer reads {read} bytes but writes {write}")]
+    SpiLengthMismatch { read: usize, write: usize },
+
+    #[error("{transfers} SPI transfers do not fit in one message")]
+    TooManySpiTransfers { transfers: usize },
+
+    #[error("There is no UART{uart}")]
+    InvalidUart { uart: u32 },
+
+    #[error("Invalid baud rate {baud_rate}")]
+    InvalidBaudRate { baud_rate: u32 },
+
+    #[error("There is no ADC{channel}")]
+    InvalidAdcChannel { channel: u32 },
+
+    #[error("ADC{channel} conversion did not complete")]
+    AdcTimeout { channel: u32 },
+
+    #[error("Invalid sample rate {rate_hz} Hz")]
+    InvalidSampleRate { rate_hz: f64 },
+
+    #[error("Cannot derive a {hz} Hz debounce clock")]
+    InvalidDebounceClock { hz: u32 },
+
+    #[error("{trigger:?} triggering is not supported by this backend")]
+    UnsupportedTrigger { trigger: Trigger },
+
+    #[error("Unknown line event id {id}")]
+    InvalidLineEvent { id: u32 },
+
+    #[error("Unexpected value {value:?} in {}", path.display())]
+    InvalidValue { path: PathBuf, value: String },
+
+    #[error("Pad {pad} is muxed to unknown function {value}")]
+    UnknownPadFunction { pad: &'static str, value: u32 },
+
+    #[error("Pad {pad} has no {function} function")]
+    NoPadFunction { pad: &'static str, function: String },
+
+    #[error("No known pad carries {pin:?}")]
+    NoPadForPin { pin: Pin },
+}
+
+impl GpioError {
+    /// Classifies a failure to open `path`.
+    pub fn open(path: impl AsRef<Path>, source: io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied { path, source },
+            _ => Self::Open { path, source },
+        }
+    }
+
+    pub fn sysfs(path: impl AsRef<Path>, source: io::Error) -> Self {
+        Self::Sysfs { path: path.as_ref().to_path_buf(), source }
+    }
+
+    /// The underlying OS error, looking through register access context.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Self::PermissionDenied { source, .. }
+            | Self::Open { source, .. }
+            | Self::Mmap { source, .. }
+            | Self::Munmap { source, .. }
+            | Self::AlreadyExported { source, .. }
+            | Self::Sysfs { source, .. }
+            | Self::Ioctl { source, .. }
+            | Self::Reactor { source, .. }
+            | Self::Lock { source, .. } => Some(source),
+            Self::Register { source, .. } | Self::PortRegister { source, .. } => source.io_error(),
+            _ => None,
+        }
+    }
+
+    /// Whether the OS refused access, which usually means falling back to a
+    /// backend that needs fewer privileges.
+    pub fn is_permission_denied(&self) -> bool {
+        self.io_error().is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+    }
+}
@@ -0,0 +1,150 @@
+//! Async edge and level waits on character-device lines.
+//!
+//! Readiness comes from registering the line request fd with the tokio
+//! reactor, so no thread is parked in `poll(2)` while waiting.
+
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use embedded_hal::digital::ErrorType;
+use embedded_hal_async::digital::Wait;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use crate::gpio_cdev::{GpioCdev, GpioChipOps, GpioLines, LineEvent, LineEventKind, LineSettings};
+use crate::{Edge, GpioError, Result};
+
+/// A single requested line that can be awaited from a tokio runtime.
+pub struct AsyncGpioLine<C: GpioChipOps> {
+    // Declared first so the fd leaves the reactor before the line is released.
+    fd: AsyncFd<RawFd>,
+    line: GpioLines<C>,
+    settings: LineSettings,
+}
+
+impl<C: GpioChipOps> GpioCdev<C> {
+    /// Requests `offset` for async use. Must be called within a tokio runtime.
+    pub fn request_async_line(
+        &self,
+        offset: u32,
+        consumer: &str,
+        settings: &LineSettings,
+    ) -> Result<AsyncGpioLine<C>> {
+        AsyncGpioLine::new(self.request_line(offset, consumer, settings)?, settings.clone())
+    }
+}
+
+impl<C: GpioChipOps> AsyncGpioLine<C> {
+    /// Wraps `line`, which was requested with `settings`. The settings are
+    /// restored after every wait, including cancelled ones.
+    pub fn new(line: GpioLines<C>, settings: LineSettings) -> Result<Self> {
+        let raw = line.fd();
+        let fd = AsyncFd::with_interest(raw, Interest::READABLE)
+            .map_err(|source| GpioError::Reactor { fd: raw, source })?;
+
+        Ok(Self { fd, line, settings })
+    }
+
+    pub fn line(&self) -> &GpioLines<C> {
+        &self.line
+    }
+
+    /// Waits for the next event with the edge detection the line was
+    /// requested with.
+    pub async fn next_event(&self) -> Result<LineEvent> {
+        loop {
+            let mut guard = self
+                .fd
+                .readable()
+                .await
+                .map_err(|source| GpioError::Reactor { fd: self.line.fd(), source })?;
+            match self.line.read_event(Some(Duration::ZERO))? {
+                Some(event) => return Ok(event),
+                None => guard.clear_ready(),
+            }
+        }
+    }
+
+    /// Switches edge detection to `edge` until the returned guard drops.
+    /// Events queued before the switch are discarded so they cannot satisfy
+    /// the new wait.
+    fn arm(&self, edge: Edge) -> Result<EdgeGuard<'_, C>> {
+        self.line.reconfigure(&LineSettings { edge, ..self.settings.clone() })?;
+        let guard = EdgeGuard { line: &self.line, settings: &self.settings };
+        while self.line.read_event(Some(Duration::ZERO))?.is_some() {}
+
+        Ok(guard)
+    }
+
+    async fn wait_for_edge(&self, edge: Edge) -> Result<LineEvent> {
+        let _guard = self.arm(edge)?;
+        loop {
+            let event = self.next_event().await?;
+            let matches = match edge {
+                Edge::None => false,
+                Edge::Rising => event.kind == LineEventKind::RisingEdge,
+                Edge::Falling => event.kind == LineEventKind::FallingEdge,
+                Edge::Both => true,
+            };
+            if matches {
+                return Ok(event);
+            }
+        }
+    }
+
+    async fn wait_for_level(&self, high: bool) -> Result<()> {
+        let edge = if high { Edge::Rising } else { Edge::Falling };
+        // Armed before sampling, so a change in between is still seen.
+        let _guard = self.arm(edge)?;
+        if self.line.get_value()? == high {
+            return Ok(());
+        }
+        loop {
+            let event = self.next_event().await?;
+            if (event.kind == LineEventKind::RisingEdge) == high {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Puts the line back to its requested settings when a wait ends, whether
+/// it completed, failed or its future was dropped.
+struct EdgeGuard<'a, C: GpioChipOps> {
+    line: &'a GpioLines<C>,
+    settings: &'a LineSettings,
+}
+
+impl<C: GpioChipOps> Drop for EdgeGuard<'_, C> {
+    fn drop(&mut self) {
+        if let Err(e) = self.line.reconfigure(self.settings) {
+            log::error!("Error trying to restore lines {:?}: {e}", self.line.offsets());
+        }
+    }
+}
+
+impl<C: GpioChipOps> ErrorType for AsyncGpioLine<C> {
+    type Error = GpioError;
+}
+
+impl<C: GpioChipOps> Wait for AsyncGpioLine<C> {
+    async fn wait_for_high(&mut self) -> Result<()> {
+        self.wait_for_level(true).await
+    }
+
+    async fn wait_for_low(&mut self) -> Result<()> {
+        self.wait_for_level(false).await
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<()> {
+        self.wait_for_edge(Edge::Rising).await.map(drop)
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<()> {
+        self.wait_for_edge(Edge::Falling).await.map(drop)
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<()> {
+        self.wait_for_edge(Edge::Both).await.map(drop)
+    }
+}
@@ -0,0 +1,614 @@
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::raw::c_char;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{Edge, GpioDirection, GpioError, GpioPort, Pin, Result, Trigger};
+
+pub const GPIO_MAX_NAME_SIZE: usize = 32;
+pub const GPIO_V2_LINES_MAX: usize = 64;
+pub const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+pub const GPIO_V2_LINE_FLAG_USED: u64 = 1 << 0;
+pub const GPIO_V2_LINE_FLAG_ACTIVE_LOW: u64 = 1 << 1;
+pub const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+pub const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+pub const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+pub const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+pub const GPIO_V2_LINE_FLAG_OPEN_DRAIN: u64 = 1 << 6;
+pub const GPIO_V2_LINE_FLAG_OPEN_SOURCE: u64 = 1 << 7;
+pub const GPIO_V2_LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+pub const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+pub const GPIO_V2_LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+pub const GPIO_V2_LINE_FLAG_EVENT_CLOCK_REALTIME: u64 = 1 << 11;
+
+pub const GPIO_V2_LINE_ATTR_ID_FLAGS: u32 = 1;
+pub const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+pub const GPIO_V2_LINE_ATTR_ID_DEBOUNCE: u32 = 3;
+
+pub const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+pub const GPIO_V2_LINE_EVENT_FALLING_EDGE: u32 = 2;
+
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | (0xB4 << 8) | nr
+}
+
+const IOC_READ: u32 = 2;
+const IOC_READ_WRITE: u32 = 3;
+
+pub const GPIO_GET_CHIPINFO_IOCTL: u32 = ioc(IOC_READ, 0x01, size_of::<GpioChipInfo>());
+pub const GPIO_V2_GET_LINE_IOCTL: u32 = ioc(IOC_READ_WRITE, 0x07, size_of::<GpioV2LineRequest>());
+pub const GPIO_V2_LINE_SET_CONFIG_IOCTL: u32 =
+    ioc(IOC_READ_WRITE, 0x0D, size_of::<GpioV2LineConfig>());
+pub const GPIO_V2_LINE_GET_VALUES_IOCTL: u32 =
+    ioc(IOC_READ_WRITE, 0x0E, size_of::<GpioV2LineValues>());
+pub const GPIO_V2_LINE_SET_VALUES_IOCTL: u32 =
+    ioc(IOC_READ_WRITE, 0x0F, size_of::<GpioV2LineValues>());
+
+/// `struct gpiochip_info` from `<linux/gpio.h>`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct GpioChipInfo {
+    pub name: [c_char; GPIO_MAX_NAME_SIZE],
+    pub label: [c_char; GPIO_MAX_NAME_SIZE],
+    pub lines: u32,
+}
+
+/// `struct gpio_v2_line_values` from `<linux/gpio.h>`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GpioV2LineValues {
+    pub bits: u64,
+    pub mask: u64,
+}
+
+/// `struct gpio_v2_line_attribute` from `<linux/gpio.h>`. The kernel union
+/// of flags, output values and debounce period is kept in `value`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GpioV2LineAttribute {
+    pub id: u32,
+    pub padding: u32,
+    pub value: u64,
+}
+
+/// `struct gpio_v2_line_config_attribute` from `<linux/gpio.h>`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GpioV2LineConfigAttribute {
+    pub attr: GpioV2LineAttribute,
+    pub mask: u64,
+}
+
+/// `struct gpio_v2_line_config` from `<linux/gpio.h>`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GpioV2LineConfig {
+    pub flags: u64,
+    pub num_attrs: u32,
+    pub padding: [u32; 5],
+    pub attrs: [GpioV2LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+/// `struct gpio_v2_line_request` from `<linux/gpio.h>`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct GpioV2LineRequest {
+    pub offsets: [u32; GPIO_V2_LINES_MAX],
+    pub consumer: [c_char; GPIO_MAX_NAME_SIZE],
+    pub config: GpioV2LineConfig,
+    pub num_lines: u32,
+    pub event_buffer_size: u32,
+    pub padding: [u32; 5],
+    pub fd: i32,
+}
+
+impl Default for GpioV2LineRequest {
+    fn default() -> Self {
+        Self {
+            offsets: [0; GPIO_V2_LINES_MAX],
+            consumer: [0; GPIO_MAX_NAME_SIZE],
+            config: GpioV2LineConfig::default(),
+            num_lines: 0,
+            event_buffer_size: 0,
+            padding: [0; 5],
+            fd: -1,
+        }
+    }
+}
+
+impl GpioV2LineRequest {
+    pub fn consumer(&self) -> String {
+        c_string(&self.consumer)
+    }
+}
+
+/// `struct gpio_v2_line_event` from `<linux/gpio.h>`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct GpioV2LineEvent {
+    pub timestamp_ns: u64,
+    pub id: u32,
+    pub offset: u32,
+    pub seqno: u32,
+    pub line_seqno: u32,
+    pub padding: [u32; 6],
+}
+
+fn c_string(raw: &[c_char]) -> String {
+    let bytes: Vec<u8> = raw.iter().map(|c| *c as u8).collect();
+    CStr::from_bytes_until_nul(&bytes)
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The ioctl layer of a GPIO character device.
+///
+/// [`DuoGpioChip`] talks to `/dev/gpiochipN`; tests can substitute a mock or
+/// point [`DuoGpioChip`] at a `gpio-sim` chip.
+pub trait GpioChipOps {
+    fn chip_info(&self) -> Result<GpioChipInfo>;
+    /// Issues `GPIO_V2_GET_LINE_IOCTL` and returns the line request fd.
+    fn get_line(&self, request: &mut GpioV2LineRequest) -> Result<RawFd>;
+    fn set_config(&self, line_fd: RawFd, config: &GpioV2LineConfig) -> Result<()>;
+    fn get_values(&self, line_fd: RawFd, values: &mut GpioV2LineValues) -> Result<()>;
+    fn set_values(&self, line_fd: RawFd, values: &GpioV2LineValues) -> Result<()>;
+    /// Reads the next edge event, waiting at most `timeout`. `None` waits
+    /// forever.
+    fn read_event(
+        &self,
+        line_fd: RawFd,
+        timeout: Option<Duration>,
+    ) -> Result<Option<GpioV2LineEvent>>;
+    /// Closes the line request fd, releasing the lines.
+    fn release(&self, line_fd: RawFd) -> Result<()>;
+}
+
+/// A `/dev/gpiochipN` device.
+pub struct DuoGpioChip {
+    path: PathBuf,
+    chip: File,
+}
+
+impl DuoGpioChip {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let chip = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| GpioError::open(&path, e))?;
+
+        Ok(Self { path, chip })
+    }
+
+    /// Opens the chip of a SoC GPIO port. The kernel registers the ports in
+    /// address order, so XGPIOA is `gpiochip0` and PWR_GPIO is `gpiochip4`.
+    pub fn for_port(port: GpioPort) -> Result<Self> {
+        Self::open(format!("/dev/gpiochip{}", port.index()))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn ioctl<T>(&self, fd: RawFd, ioctl: &'static str, request: u32, arg: *mut T) -> Result<()> {
+        let result = unsafe { libc::ioctl(fd, request as _, arg) };
+
+        if result == -1 {
+            Err(GpioError::Ioctl { ioctl, source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl GpioChipOps for DuoGpioChip {
+    fn chip_info(&self) -> Result<GpioChipInfo> {
+        let mut info = GpioChipInfo { name: [0; 32], label: [0; 32], lines: 0 };
+        self.ioctl(
+            self.chip.as_raw_fd(),
+            "GPIO_GET_CHIPINFO_IOCTL",
+            GPIO_GET_CHIPINFO_IOCTL,
+            &mut info,
+        )?;
+
+        Ok(info)
+    }
+
+    fn get_line(&self, request: &mut GpioV2LineRequest) -> Result<RawFd> {
+        self.ioctl(
+            self.chip.as_raw_fd(),
+            "GPIO_V2_GET_LINE_IOCTL",
+            GPIO_V2_GET_LINE_IOCTL,
+            request,
+        )?;
+
+        Ok(request.fd)
+    }
+
+    fn set_config(&self, line_fd: RawFd, config: &GpioV2LineConfig) -> Result<()> {
+        let mut config = *config;
+        self.ioctl(
+            line_fd,
+            "GPIO_V2_LINE_SET_CONFIG_IOCTL",
+            GPIO_V2_LINE_SET_CONFIG_IOCTL,
+            &mut config,
+        )
+    }
+
+    fn get_values(&self, line_fd: RawFd, values: &mut GpioV2LineValues) -> Result<()> {
+        self.ioctl(line_fd, "GPIO_V2_LINE_GET_VALUES_IOCTL", GPIO_V2_LINE_GET_VALUES_IOCTL, values)
+    }
+
+    fn set_values(&self, line_fd: RawFd, values: &GpioV2LineValues) -> Result<()> {
+        let mut values = *values;
+        self.ioctl(
+            line_fd,
+            "GPIO_V2_LINE_SET_VALUES_IOCTL",
+            GPIO_V2_LINE_SET_VALUES_IOCTL,
+            &mut values,
+        )
+    }
+
+    fn read_event(
+        &self,
+        line_fd: RawFd,
+        timeout: Option<Duration>,
+    ) -> Result<Option<GpioV2LineEvent>> {
+        let mut poll_fd = libc::pollfd { fd: line_fd, events: libc::POLLIN, revents: 0 };
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+
+        loop {
+            let result = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+
+            if result == 0 {
+                return Ok(None);
+            }
+            if result > 0 {
+                break;
+            }
+
+            let source = io::Error::last_os_error();
+            if source.kind() != io::ErrorKind::Interrupted {
+                return Err(GpioError::Ioctl { ioctl: "poll", source });
+            }
+        }
+
+        let mut event = GpioV2LineEvent::default();
+        let len = size_of::<GpioV2LineEvent>();
+        let read = unsafe { libc::read(line_fd, &mut event as *mut _ as *mut libc::c_void, len) };
+
+        if read == len as isize {
+            Ok(Some(event))
+        } else {
+            Err(GpioError::Ioctl { ioctl: "read", source: io::Error::last_os_error() })
+        }
+    }
+
+    fn release(&self, line_fd: RawFd) -> Result<()> {
+        if unsafe { libc::close(line_fd) } == -1 {
+            Err(GpioError::Ioctl { ioctl: "close", source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Bias {
+    /// Leave the bias as configured by the bootloader or device tree.
+    #[default]
+    AsIs,
+    Disabled,
+    PullUp,
+    PullDown,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Drive {
+    #[default]
+    PushPull,
+    OpenDrain,
+    OpenSource,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EventClock {
+    #[default]
+    Monotonic,
+    Realtime,
+}
+
+/// Configuration applied to every line of a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineSettings {
+    pub direction: GpioDirection,
+    /// Initial level of output lines, before `active_low` is applied.
+    pub output_value: bool,
+    pub active_low: bool,
+    pub bias: Bias,
+    /// Only meaningful for outputs.
+    pub drive: Drive,
+    /// Only meaningful for inputs.
+    pub edge: Edge,
+    pub debounce: Option<Duration>,
+    pub event_clock: EventClock,
+}
+
+impl Default for LineSettings {
+    fn default() -> Self {
+        Self {
+            direction: GpioDirection::GpioInput,
+            output_value: false,
+            active_low: false,
+            bias: Bias::AsIs,
+            drive: Drive::PushPull,
+            edge: Edge::None,
+            debounce: None,
+            event_clock: EventClock::Monotonic,
+        }
+    }
+}
+
+impl LineSettings {
+    pub fn input() -> Self {
+        Self::default()
+    }
+
+    pub fn output(value: bool) -> Self {
+        Self { direction: GpioDirection::GpioOutput, output_value: value, ..Self::default() }
+    }
+
+    /// Edge detection for `trigger`. The kernel only reports edges, so level
+    /// triggers fail with [`GpioError::UnsupportedTrigger`].
+    pub fn with_trigger(self, trigger: Trigger) -> Result<Self> {
+        Ok(Self { edge: trigger.try_into()?, ..self })
+    }
+
+    /// Encodes the settings for a request covering `num_lines` lines.
+    pub fn to_config(&self, num_lines: usize) -> GpioV2LineConfig {
+        let all_lines = if num_lines >= 64 { u64::MAX } else { (1 << num_lines) - 1 };
+        let mut flags = match self.direction {
+            GpioDirection::GpioInput => {
+                GPIO_V2_LINE_FLAG_INPUT
+                    | match self.edge {
+                        Edge::None => 0,
+                        Edge::Rising => GPIO_V2_LINE_FLAG_EDGE_RISING,
+                        Edge::Falling => GPIO_V2_LINE_FLAG_EDGE_FALLING,
+                        Edge::Both => {
+                            GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING
+                        },
+                    }
+            },
+            GpioDirection::GpioOutput => {
+                GPIO_V2_LINE_FLAG_OUTPUT
+                    | match self.drive {
+                        Drive::PushPull => 0,
+                        Drive::OpenDrain => GPIO_V2_LINE_FLAG_OPEN_DRAIN,
+                        Drive::OpenSource => GPIO_V2_LINE_FLAG_OPEN_SOURCE,
+                    }
+            },
+        };
+        if self.active_low {
+            flags |= GPIO_V2_LINE_FLAG_ACTIVE_LOW;
+        }
+        flags |= match self.bias {
+            Bias::AsIs => 0,
+            Bias::Disabled => GPIO_V2_LINE_FLAG_BIAS_DISABLED,
+            Bias::PullUp => GPIO_V2_LINE_FLAG_BIAS_PULL_UP,
+            Bias::PullDown => GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN,
+        };
+        if self.event_clock == EventClock::Realtime {
+            flags |= GPIO_V2_LINE_FLAG_EVENT_CLOCK_REALTIME;
+        }
+        let mut config = GpioV2LineConfig { flags, ..Default::default() };
+
+        let mut attrs = Vec::new();
+        if self.direction == GpioDirection::GpioOutput {
+            let values = if self.output_value { all_lines } else { 0 };
+            attrs.push((GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES, values));
+        }
+        if let Some(debounce) = self.debounce {
+            let period_us = debounce.as_micros().min(u32::MAX as u128) as u64;
+            attrs.push((GPIO_V2_LINE_ATTR_ID_DEBOUNCE, period_us));
+        }
+        for (slot, (id, value)) in config.attrs.iter_mut().zip(attrs.iter()) {
+            *slot = GpioV2LineConfigAttribute {
+                attr: GpioV2LineAttribute { id: *id, padding: 0, value: *value },
+                mask: all_lines,
+            };
+        }
+        config.num_attrs = attrs.len() as u32;
+
+        config
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEventKind {
+    RisingEdge,
+    FallingEdge,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineEvent {
+    pub kind: LineEventKind,
+    /// Chip offset of the line that changed.
+    pub offset: u32,
+    /// Kernel timestamp, on the clock chosen with
+    /// [`LineSettings::event_clock`].
+    pub timestamp: Duration,
+    pub seqno: u32,
+    pub line_seqno: u32,
+}
+
+impl TryFrom<GpioV2LineEvent> for LineEvent {
+    type Error = GpioError;
+
+    fn try_from(event: GpioV2LineEvent) -> Result<Self> {
+        let kind = match event.id {
+            GPIO_V2_LINE_EVENT_RISING_EDGE => LineEventKind::RisingEdge,
+            GPIO_V2_LINE_EVENT_FALLING_EDGE => LineEventKind::FallingEdge,
+            id => return Err(GpioError::InvalidLineEvent { id }),
+        };
+
+        Ok(Self {
+            kind,
+            offset: event.offset,
+            timestamp: Duration::from_nanos(event.timestamp_ns),
+            seqno: event.seqno,
+            line_seqno: event.line_seqno,
+        })
+    }
+}
+
+pub struct ChipInfo {
+    pub name: String,
+    pub label: String,
+    pub lines: u32,
+}
+
+/// A GPIO character device, used to request lines.
+pub struct GpioCdev<C: GpioChipOps> {
+    ops: Arc<C>,
+}
+
+impl GpioCdev<DuoGpioChip> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(DuoGpioChip::open(path)?))
+    }
+}
+
+impl<C: GpioChipOps> GpioCdev<C> {
+    pub fn new(ops: C) -> Self {
+        Self { ops: Arc::new(ops) }
+    }
+
+    pub fn info(&self) -> Result<ChipInfo> {
+        let info = self.ops.chip_info()?;
+
+        Ok(ChipInfo { name: c_string(&info.name), label: c_string(&info.label), lines: info.lines })
+    }
+
+    pub fn request_line(
+        &self,
+        offset: u32,
+        consumer: &str,
+        settings: &LineSettings,
+    ) -> Result<GpioLines<C>> {
+        self.request_lines(&[offset], consumer, settings)
+    }
+
+    /// Requests `offsets` as one set of lines, labelled `consumer` in
+    /// `gpioinfo`. The lines are released when the returned handle drops.
+    pub fn request_lines(
+        &self,
+        offsets: &[u32],
+        consumer: &str,
+        settings: &LineSettings,
+    ) -> Result<GpioLines<C>> {
+        if offsets.is_empty() || offsets.len() > GPIO_V2_LINES_MAX {
+            return Err(GpioError::InvalidLineCount { count: offsets.len() });
+        }
+
+        let mut request = GpioV2LineRequest::default();
+        request.offsets[..offsets.len()].copy_from_slice(offsets);
+        for (dst, src) in
+            request.consumer.iter_mut().zip(consumer.bytes().take(GPIO_MAX_NAME_SIZE - 1))
+        {
+            *dst = src as c_char;
+        }
+        request.config = settings.to_config(offsets.len());
+        request.num_lines = offsets.len() as u32;
+
+        let fd = self.ops.get_line(&mut request)?;
+
+        Ok(GpioLines { ops: Arc::clone(&self.ops), fd, offsets: offsets.to_vec() })
+    }
+
+    /// Requests a SoC pin, whose line offset on its port's chip is the pin's
+    /// line number.
+    pub fn request_pin(
+        &self,
+        pin: Pin,
+        consumer: &str,
+        settings: &LineSettings,
+    ) -> Result<GpioLines<C>> {
+        self.request_line(pin.line(), consumer, settings)
+    }
+}
+
+/// Lines held through a line request fd.
+///
+/// Values are indexed by position in the requested offsets, bit 0 being the
+/// first line. Reads and writes are logical, i.e. `active_low` is applied
+/// by the kernel.
+pub struct GpioLines<C: GpioChipOps> {
+    ops: Arc<C>,
+    fd: RawFd,
+    offsets: Vec<u32>,
+}
+
+impl<C: GpioChipOps> GpioLines<C> {
+    pub fn offsets(&self) -> &[u32] {
+        &self.offsets
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    fn all_lines(&self) -> u64 {
+        if self.offsets.len() >= 64 {
+            u64::MAX
+        } else {
+            (1 << self.offsets.len()) - 1
+        }
+    }
+
+    pub fn get_values(&self) -> Result<u64> {
+        let mut values = GpioV2LineValues { bits: 0, mask: self.all_lines() };
+        self.ops.get_values(self.fd, &mut values)?;
+
+        Ok(values.bits & values.mask)
+    }
+
+    pub fn set_values(&self, mask: u64, bits: u64) -> Result<()> {
+        let values = GpioV2LineValues { bits, mask: mask & self.all_lines() };
+        self.ops.set_values(self.fd, &values)
+    }
+
+    /// Value of the first line.
+    pub fn get_value(&self) -> Result<bool> {
+        Ok(self.get_values()? & 1 != 0)
+    }
+
+    /// Sets the first line.
+    pub fn set_value(&self, value: bool) -> Result<()> {
+        self.set_values(1, value as u64)
+    }
+
+    pub fn reconfigure(&self, settings: &LineSettings) -> Result<()> {
+        self.ops.set_config(self.fd, &settings.to_config(self.offsets.len()))
+    }
+
+    /// Waits for the next edge event on any of the lines. Returns `None`
+    /// once `timeout` elapses; `None` waits forever.
+    pub fn read_event(&self, timeout: Option<Duration>) -> Result<Option<LineEvent>> {
+        self.ops.read_event(self.fd, timeout)?.map(LineEvent::try_from).transpose()
+    }
+}
+
+impl<C: GpioChipOps> Drop for GpioLines<C> {
+    fn drop(&mut self) {
+        if let Err(e) = self.ops.release(self.fd) {
+            log::error!("Error trying to release lines {:?}: {e}", self.offsets);
+        }
+    }
+}
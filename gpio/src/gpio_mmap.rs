@@ -1,11 +1,19 @@
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
+use std::marker::PhantomData;
 use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::io::AsRawFd;
+use std::path::Path;
 use std::ptr;
+use std::sync::{Arc, Mutex};
 
-use anyhow::{anyhow, Context, Result};
 use libc::{c_void, mmap, munmap, off_t, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
 
+use crate::claim::ClaimScope;
+use crate::{GpioError, RegisterIo, Result};
+
+const DEV_MEM: &str = "/dev/mem";
+
 pub struct DevMem {
     dev_mem: File,
     page_size: usize,
@@ -13,12 +21,19 @@ pub struct DevMem {
 
 impl DevMem {
     pub fn new() -> Result<Self> {
+        Self::open(DEV_MEM)
+    }
+
+    /// Maps physical addresses as offsets into `path` instead of
+    /// `/dev/mem`, e.g. a plain file standing in for device memory.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
         let dev_mem = OpenOptions::new()
             .read(true)
             .write(true)
             .custom_flags(libc::O_SYNC)
-            .open("/dev/mem")
-            .context("Error opening /dev/mem")?;
+            .open(path)
+            .map_err(|e| GpioError::open(path, e))?;
 
         let page_size = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) } as usize;
 
@@ -26,8 +41,7 @@ impl DevMem {
     }
 
     pub fn mem_write(&self, addr: usize, val: u32) -> Result<()> {
-        let virt_addr =
-            self.dev_mmap(addr, 4).context(format!("Failed to write {val} at address {addr}"))?;
+        let virt_addr = self.dev_mmap(addr, 4)?;
 
         unsafe {
             ptr::write_volatile(virt_addr as *mut u32, val);
@@ -37,8 +51,7 @@ impl DevMem {
     }
 
     pub fn mem_read(&self, addr: usize) -> Result<u32> {
-        let virt_addr =
-            self.dev_mmap(addr, 4).context(format!("Failed to read from address {addr}"))?;
+        let virt_addr = self.dev_mmap(addr, 4)?;
 
         let val = unsafe { ptr::read_volatile(virt_addr as *mut u32) };
 
@@ -47,6 +60,12 @@ impl DevMem {
         Ok(val)
     }
 
+    /// Maps the physical window `[addr, addr + len)` once, so its registers
+    /// can be accessed without a mmap/munmap round trip per access.
+    pub fn map_region(&self, addr: usize, len: usize) -> Result<MmioRegion> {
+        MmioRegion::new(self, addr, len)
+    }
+
     fn dev_mmap(&self, addr: usize, len: usize) -> Result<*mut c_void> {
         let offset = addr & !(self.page_size - 1);
         let map_len = len + addr - offset;
@@ -63,8 +82,8 @@ impl DevMem {
         };
 
         if map_base == MAP_FAILED || map_base.is_null() {
-            let err = std::io::Error::last_os_error();
-            Err(anyhow!("Unable to allocate memory: {err}"))
+            let source = std::io::Error::last_os_error();
+            Err(GpioError::Mmap { addr, len, source })
         } else {
             let mapped_addr = map_base as usize + addr - offset;
             Ok(mapped_addr as *mut c_void)
@@ -78,18 +97,181 @@ impl DevMem {
         let result = unsafe { munmap(addr as *mut c_void, unmap_len) };
 
         if result == -1 {
-            let err = std::io::Error::last_os_error();
-            Err(anyhow!("Error running munmap: {err}"))
+            let source = std::io::Error::last_os_error();
+            Err(GpioError::Munmap { addr, source })
         } else {
             Ok(())
         }
     }
 }
 
-impl Drop for DevMem {
+impl RegisterIo for DevMem {
+    const CROSS_PROCESS: bool = true;
+
+    fn map(_base: usize, _len: usize) -> Result<Self> {
+        Self::new()
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        self.mem_read(addr)
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        self.mem_write(addr, val)
+    }
+}
+
+/// A physical register window that stays mapped until it is dropped.
+///
+/// Addresses passed to the accessors are physical addresses, the same ones
+/// handed out by [`DuoGpio`](crate::duo::DuoGpio), and must fall inside the
+/// mapped window.
+pub struct MmioRegion {
+    base: usize,
+    len: usize,
+    map_base: *mut c_void,
+    map_len: usize,
+    virt_base: usize,
+}
+
+impl MmioRegion {
+    pub fn new(dev: &DevMem, base: usize, len: usize) -> Result<Self> {
+        let virt_addr = dev.dev_mmap(base, len)?;
+
+        let offset = base & !(dev.page_size - 1);
+        let map_base = (virt_addr as usize - (base - offset)) as *mut c_void;
+        let map_len = len + base - offset;
+
+        Ok(Self { base, len, map_base, map_len, virt_base: virt_addr as usize })
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.base && addr + 4 <= self.base + self.len
+    }
+
+    /// Returns a volatile accessor for the 32-bit register at `addr`.
+    pub fn register(&self, addr: usize) -> Result<Register<'_>> {
+        if !addr.is_multiple_of(4) {
+            return Err(GpioError::Unaligned { addr });
+        }
+        if !self.contains(addr) {
+            return Err(GpioError::OutOfRange { addr, base: self.base, len: self.len });
+        }
+
+        let ptr = (self.virt_base + addr - self.base) as *mut u32;
+        Ok(Register { ptr, _region: PhantomData })
+    }
+}
+
+impl RegisterIo for MmioRegion {
+    const CROSS_PROCESS: bool = true;
+
+    fn map(base: usize, len: usize) -> Result<Self> {
+        DevMem::new()?.map_region(base, len)
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        Ok(self.register(addr)?.read())
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        self.register(addr)?.write(val);
+        Ok(())
+    }
+}
+
+// The mapping is plain device memory accessed with volatile reads and
+// writes; handles serialise their read-modify-write cycles through
+// `port_lock`, so sharing a region between threads is sound.
+unsafe impl Send for MmioRegion {}
+unsafe impl Sync for MmioRegion {}
+
+impl Drop for MmioRegion {
     fn drop(&mut self) {
-        unsafe {
-            libc::close(self.dev_mem.as_raw_fd());
+        let result = unsafe { munmap(self.map_base, self.map_len) };
+
+        if result == -1 {
+            let err = std::io::Error::last_os_error();
+            log::error!("Error running munmap on region {:#010x}: {err}", self.base);
         }
     }
 }
+
+/// A single 32-bit register inside a [`MmioRegion`].
+///
+/// Every access is volatile; the borrow keeps the mapping alive.
+pub struct Register<'a> {
+    ptr: *mut u32,
+    _region: PhantomData<&'a MmioRegion>,
+}
+
+impl Register<'_> {
+    pub fn read(&self) -> u32 {
+        unsafe { ptr::read_volatile(self.ptr) }
+    }
+
+    pub fn write(&self, val: u32) {
+        unsafe { ptr::write_volatile(self.ptr, val) }
+    }
+
+    /// Read-modify-write of the register.
+    pub fn modify(&self, f: impl FnOnce(u32) -> u32) {
+        self.write(f(self.read()))
+    }
+}
+
+/// Register backend over plain memory, for running the drivers off-target.
+///
+/// Unwritten registers read as zero. Clones share the same storage, so a
+/// test can keep one clone to inspect what a driver wrote through another.
+#[derive(Clone, Debug, Default)]
+pub struct MemRegisters {
+    regs: Arc<Mutex<HashMap<usize, u32>>>,
+}
+
+impl MemRegisters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Presets a register without going through [`RegisterIo`].
+    pub fn set(&self, addr: usize, val: u32) {
+        self.regs.lock().unwrap().insert(addr, val);
+    }
+
+    /// Reads a register without going through [`RegisterIo`].
+    pub fn get(&self, addr: usize) -> u32 {
+        self.regs.lock().unwrap().get(&addr).copied().unwrap_or(0)
+    }
+}
+
+impl RegisterIo for MemRegisters {
+    fn map(_base: usize, _len: usize) -> Result<Self> {
+        Ok(Self::new())
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        Ok(self.get(addr))
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        self.set(addr, val);
+        Ok(())
+    }
+
+    fn claim_scope(&self) -> Option<ClaimScope> {
+        Some(ClaimScope::Instance(Arc::as_ptr(&self.regs) as usize))
+    }
+}
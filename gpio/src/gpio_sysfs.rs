@@ -1,8 +1,8 @@
 use std::path::Path;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-
-use crate::FileSystemOps;
+use crate::claim::PinClaim;
+use crate::{Device, DropPolicy, Edge, FileSystemOps, GpioError, Pin, Result, Trigger};
 
 const GPIO_PATH: &str = "/sys/class/gpio";
 const DIRECTION_IN: &str = "in";
@@ -11,25 +11,108 @@ const EXPORT: &str = "export";
 const UNEXPORT: &str = "unexport";
 const VALUE: &str = "value";
 const DIRECTION: &str = "direction";
+const EDGE: &str = "edge";
+
+/// Value of the sysfs `edge` attribute.
+fn edge_attribute(edge: Edge) -> &'static str {
+    match edge {
+        Edge::None => "none",
+        Edge::Rising => "rising",
+        Edge::Falling => "falling",
+        Edge::Both => "both",
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EdgeEvent {
+    /// Level read back right after the edge was signalled.
+    pub level: u8,
+    pub timestamp: Instant,
+}
 
 pub struct GpioSysfs<F: FileSystemOps> {
     gpio_pin: u32,
     gpio_label: String,
     fs_ops: F,
+    drop_policy: DropPolicy,
+    /// The line was already exported when the handle was opened.
+    adopted: bool,
+    _claim: Option<PinClaim>,
 }
 
 impl<F: FileSystemOps> GpioSysfs<F> {
+    /// Exports the global sysfs GPIO `gpio_pin`, which must map to a line
+    /// that exists on the SoC. The line is claimed first, across processes
+    /// on the real sysfs, see [`PinClaim`].
+    ///
+    /// A line that is still exported, e.g. by a previous run that dropped
+    /// its handle with [`DropPolicy::LeaveAsIs`], is adopted as it is: the
+    /// claim proves nobody else holds it, and its direction and value are
+    /// left alone.
     pub fn new(gpio_pin: u32, fs_ops: F) -> Result<Self> {
+        Self::from_pin(Pin::from_sysfs(gpio_pin)?, fs_ops)
+    }
+
+    /// Like [`GpioSysfs::new`], for a line that was already validated, e.g.
+    /// one that only exists on some boards, see [`Pin::on`].
+    pub fn from_pin(pin: Pin, fs_ops: F) -> Result<Self> {
+        let gpio_pin = pin.sysfs_number();
+        let claim = PinClaim::take_for(pin, fs_ops.claim_scope())?;
+        // Export before building the handle, so a failed export does not
+        // reset and unexport a line that someone else owns.
+        let adopted = match Self::export_gpio(gpio_pin, &fs_ops) {
+            Ok(()) => false,
+            Err(GpioError::AlreadyExported { .. }) => true,
+            Err(e) => return Err(e),
+        };
         let gpio_label = format!("gpio{gpio_pin}");
-        let gpio = GpioSysfs { gpio_pin, gpio_label, fs_ops };
-        gpio.export_gpio()?;
 
-        Ok(gpio)
+        Ok(GpioSysfs {
+            gpio_pin,
+            gpio_label,
+            fs_ops,
+            drop_policy: DropPolicy::default(),
+            adopted,
+            _claim: claim,
+        })
+    }
+
+    /// Exports a pin by board name, see [`Device::pin`].
+    pub fn open(device: Device, name: &str, fs_ops: F) -> Result<Self> {
+        Self::from_pin(device.pin(name)?, fs_ops)
+    }
+
+    /// Sets what happens to the line on drop. [`DropPolicy::Restore`] puts
+    /// the export back as it was when the handle was opened without
+    /// touching the direction, and [`DropPolicy::LeaveAsIs`] keeps the line
+    /// exported and driven.
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    pub fn set_drop_policy(&mut self, policy: DropPolicy) {
+        self.drop_policy = policy;
     }
 
-    fn export_gpio(&self) -> Result<()> {
+    pub fn drop_policy(&self) -> DropPolicy {
+        self.drop_policy
+    }
+
+    /// Whether the line was already exported when the handle was opened.
+    pub fn is_adopted(&self) -> bool {
+        self.adopted
+    }
+
+    fn export_gpio(gpio_pin: u32, fs_ops: &F) -> Result<()> {
         let path = Path::new(GPIO_PATH).join(EXPORT);
-        self.fs_ops.write(&path, self.gpio_pin.to_string().as_bytes())
+        fs_ops.write(&path, gpio_pin.to_string().as_bytes()).map_err(|e| match e {
+            // The kernel answers EBUSY when the line is already exported.
+            GpioError::Sysfs { source, .. } if source.raw_os_error() == Some(libc::EBUSY) => {
+                GpioError::AlreadyExported { pin: gpio_pin, source }
+            },
+            e => e,
+        })
     }
 
     fn set_gpio_direction(&self, direction: &str) -> Result<()> {
@@ -55,6 +138,54 @@ impl<F: FileSystemOps> GpioSysfs<F> {
         self.fs_ops.read_to_string(&path)
     }
 
+    /// Selects the transitions reported by [`wait_for_edge`]. The pin must
+    /// be an input.
+    ///
+    /// [`wait_for_edge`]: GpioSysfs::wait_for_edge
+    pub fn set_edge(&self, edge: Edge) -> Result<()> {
+        let path = Path::new(GPIO_PATH).join(&self.gpio_label).join(EDGE);
+        self.fs_ops.write(&path, edge_attribute(edge).as_bytes())
+    }
+
+    /// Like [`set_edge`], for the edge triggers. Level triggers fail with
+    /// [`GpioError::UnsupportedTrigger`].
+    ///
+    /// [`set_edge`]: GpioSysfs::set_edge
+    pub fn set_trigger(&self, trigger: Trigger) -> Result<()> {
+        self.set_edge(trigger.try_into()?)
+    }
+
+    /// Blocks until an edge selected with [`set_edge`] occurs, or until
+    /// `timeout` elapses, in which case `None` is returned. A `timeout` of
+    /// `None` waits forever.
+    ///
+    /// Only edges that happen while waiting are reported.
+    ///
+    /// [`set_edge`]: GpioSysfs::set_edge
+    pub fn wait_for_edge(&self, timeout: Option<Duration>) -> Result<Option<EdgeEvent>> {
+        let path = Path::new(GPIO_PATH).join(&self.gpio_label).join(VALUE);
+        if !self.fs_ops.poll_priority(&path, timeout)? {
+            return Ok(None);
+        }
+        let timestamp = Instant::now();
+        let level = self.read_level()?;
+
+        Ok(Some(EdgeEvent { level, timestamp }))
+    }
+
+    /// Reads `value` as 0 or 1.
+    pub fn read_level(&self) -> Result<u8> {
+        let value = self.read_gpio_value()?;
+        match value.trim() {
+            "0" => Ok(0),
+            "1" => Ok(1),
+            _ => {
+                let path = Path::new(GPIO_PATH).join(&self.gpio_label).join(VALUE);
+                Err(GpioError::InvalidValue { path, value })
+            },
+        }
+    }
+
     pub fn unexport_gpio(&self) -> Result<()> {
         let path = Path::new(GPIO_PATH).join(UNEXPORT);
         self.fs_ops.write(&path, self.gpio_pin.to_string().as_bytes())
@@ -63,9 +194,16 @@ impl<F: FileSystemOps> GpioSysfs<F> {
 
 impl<F: FileSystemOps> Drop for GpioSysfs<F> {
     fn drop(&mut self) {
-        if let Err(e) = self.set_pin_mode_input() {
-            log::error!("Error trying to reset direction: {e}");
-        };
+        if self.drop_policy == DropPolicy::LeaveAsIs
+            || (self.drop_policy == DropPolicy::Restore && self.adopted)
+        {
+            return;
+        }
+        if self.drop_policy == DropPolicy::ResetToInput {
+            if let Err(e) = self.set_pin_mode_input() {
+                log::error!("Error trying to reset direction: {e}");
+            };
+        }
         if let Err(e) = self.unexport_gpio() {
             log::error!("Error trying to unexport pin {}: {e}", self.gpio_pin);
         };
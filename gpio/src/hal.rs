@@ -0,0 +1,276 @@
+//! `embedded-hal` traits for the register and sysfs backends.
+
+use std::mem;
+use std::ops::Range;
+
+use embedded_hal::digital::{self, ErrorKind, ErrorType, InputPin, OutputPin, StatefulOutputPin};
+use embedded_hal::i2c::{
+    self, I2c, NoAcknowledgeSource, Operation, SevenBitAddress, TenBitAddress,
+};
+use embedded_hal::pwm::{self, SetDutyCycle};
+use embedded_hal::spi::{self, SpiDevice};
+
+use crate::duo::MilkVDuoGpio;
+use crate::gpio_sysfs::GpioSysfs;
+use crate::i2c::{is_nack, I2cAddress, I2cBus, I2cBusOps, I2cMessage};
+use crate::pwm::{Pwm, PwmRegisters, PwmSysfs};
+use crate::spi::{Spi, SpiDevOps, SpiTransfer};
+use crate::{FileSystemOps, Gpio, GpioError, RegisterIo};
+
+impl digital::Error for GpioError {
+    fn kind(&self) -> ErrorKind {
+        // embedded-hal 1.0 has no finer-grained digital error kinds.
+        ErrorKind::Other
+    }
+}
+
+impl<R: RegisterIo> ErrorType for MilkVDuoGpio<'_, R> {
+    type Error = GpioError;
+}
+
+impl<R: RegisterIo> OutputPin for MilkVDuoGpio<'_, R> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.write_pin(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.write_pin(true)
+    }
+}
+
+impl<R: RegisterIo> StatefulOutputPin for MilkVDuoGpio<'_, R> {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_output_latch()? != 0)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_output_latch()? == 0)
+    }
+}
+
+impl<R: RegisterIo> InputPin for MilkVDuoGpio<'_, R> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_pin()? != 0)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_pin()? == 0)
+    }
+}
+
+impl<F: FileSystemOps> ErrorType for GpioSysfs<F> {
+    type Error = GpioError;
+}
+
+impl<F: FileSystemOps> OutputPin for GpioSysfs<F> {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.write_gpio_value(0)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.write_gpio_value(1)
+    }
+}
+
+/// sysfs reads back the driven level when the pin is an output.
+impl<F: FileSystemOps> StatefulOutputPin for GpioSysfs<F> {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_level()? == 1)
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_level()? == 0)
+    }
+}
+
+impl<F: FileSystemOps> InputPin for GpioSysfs<F> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_level()? == 1)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.read_level()? == 0)
+    }
+}
+
+impl pwm::Error for GpioError {
+    fn kind(&self) -> pwm::ErrorKind {
+        pwm::ErrorKind::Other
+    }
+}
+
+/// Duty cycles are scaled over the full `u16` range; the backends convert
+/// to controller cycles or nanoseconds.
+fn set_duty<P: Pwm>(pwm: &P, duty: u16) -> Result<(), GpioError> {
+    pwm.set_duty_fraction(f64::from(duty) / f64::from(u16::MAX))
+}
+
+impl<R: RegisterIo> pwm::ErrorType for PwmRegisters<R> {
+    type Error = GpioError;
+}
+
+impl<R: RegisterIo> SetDutyCycle for PwmRegisters<R> {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        set_duty(self, duty)
+    }
+}
+
+impl<F: FileSystemOps> pwm::ErrorType for PwmSysfs<F> {
+    type Error = GpioError;
+}
+
+impl<F: FileSystemOps> SetDutyCycle for PwmSysfs<F> {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+        set_duty(self, duty)
+    }
+}
+
+impl i2c::Error for GpioError {
+    fn kind(&self) -> i2c::ErrorKind {
+        if is_nack(self) {
+            return i2c::ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown);
+        }
+        // Adapters report a lost arbitration as EAGAIN.
+        match self.io_error().and_then(std::io::Error::raw_os_error) {
+            Some(libc::EAGAIN) => i2c::ErrorKind::ArbitrationLoss,
+            _ => i2c::ErrorKind::Other,
+        }
+    }
+}
+
+impl<B: I2cBusOps> i2c::ErrorType for I2cBus<B> {
+    type Error = GpioError;
+}
+
+/// embedded-hal joins adjacent operations of the same kind without a
+/// repeated start, but every `I2C_RDWR` message begins with one, so each run
+/// of reads or writes goes out as a single message.
+fn i2c_transaction<B: I2cBusOps>(
+    bus: &I2cBus<B>,
+    address: I2cAddress,
+    operations: &mut [Operation<'_>],
+) -> Result<(), GpioError> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for (i, operation) in operations.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run)
+                if mem::discriminant(&operations[run.start]) == mem::discriminant(operation) =>
+            {
+                run.end = i + 1;
+            },
+            _ => runs.push(i..i + 1),
+        }
+    }
+
+    let mut buffers: Vec<Vec<u8>> = runs
+        .iter()
+        .map(|run| {
+            let mut buf = Vec::new();
+            for operation in &operations[run.clone()] {
+                match operation {
+                    Operation::Read(read) => buf.resize(buf.len() + read.len(), 0),
+                    Operation::Write(write) => buf.extend_from_slice(write),
+                }
+            }
+            buf
+        })
+        .collect();
+    let mut messages: Vec<I2cMessage<'_>> = runs
+        .iter()
+        .zip(&mut buffers)
+        .map(|(run, buf)| match operations[run.start] {
+            Operation::Read(_) => I2cMessage::Read(buf),
+            Operation::Write(_) => I2cMessage::Write(buf),
+        })
+        .collect();
+    bus.transaction(address, &mut messages)?;
+    drop(messages);
+
+    for (run, buf) in runs.into_iter().zip(&buffers) {
+        let mut data = &buf[..];
+        for operation in &mut operations[run] {
+            if let Operation::Read(read) = operation {
+                let (head, rest) = data.split_at(read.len());
+                read.copy_from_slice(head);
+                data = rest;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl<B: I2cBusOps> I2c<SevenBitAddress> for I2cBus<B> {
+    fn transaction(
+        &mut self,
+        address: SevenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        i2c_transaction(self, I2cAddress::seven_bit(address)?, operations)
+    }
+}
+
+impl<B: I2cBusOps> I2c<TenBitAddress> for I2cBus<B> {
+    fn transaction(
+        &mut self,
+        address: TenBitAddress,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        i2c_transaction(self, I2cAddress::ten_bit(address)?, operations)
+    }
+}
+
+impl spi::Error for GpioError {
+    fn kind(&self) -> spi::ErrorKind {
+        spi::ErrorKind::Other
+    }
+}
+
+impl<D: SpiDevOps, G: Gpio> spi::ErrorType for Spi<D, G> {
+    type Error = GpioError;
+}
+
+impl<D: SpiDevOps, G: Gpio> SpiDevice for Spi<D, G> {
+    fn transaction(&mut self, operations: &mut [spi::Operation<'_, u8>]) -> Result<(), GpioError> {
+        let mut transfers: Vec<SpiTransfer<'_>> = Vec::with_capacity(operations.len());
+        for operation in operations {
+            match operation {
+                spi::Operation::Read(buf) => transfers.push(SpiTransfer::read(buf)),
+                spi::Operation::Write(buf) => transfers.push(SpiTransfer::write(buf)),
+                // spidev needs equal lengths, so the longer side finishes in
+                // a transfer of its own with the chip select still asserted.
+                spi::Operation::Transfer(read, write) => {
+                    let n = read.len().min(write.len());
+                    let (read, read_rest) = read.split_at_mut(n);
+                    let (write, write_rest) = write.split_at(n);
+                    transfers.push(SpiTransfer::transfer(read, write));
+                    if !read_rest.is_empty() {
+                        transfers.push(SpiTransfer::read(read_rest));
+                    }
+                    if !write_rest.is_empty() {
+                        transfers.push(SpiTransfer::write(write_rest));
+                    }
+                },
+                spi::Operation::TransferInPlace(buf) => {
+                    transfers.push(SpiTransfer::transfer_in_place(buf))
+                },
+                spi::Operation::DelayNs(ns) => {
+                    let us = u16::try_from(ns.div_ceil(1000)).unwrap_or(u16::MAX);
+                    match transfers.last_mut() {
+                        Some(last) => last.delay_us = last.delay_us.saturating_add(us),
+                        None => transfers.push(SpiTransfer::delay(us)),
+                    }
+                },
+            }
+        }
+        Spi::transaction(self, &mut transfers)
+    }
+}
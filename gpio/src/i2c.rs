@@ -0,0 +1,438 @@
+//! I2C masters through the kernel's `/dev/i2c-N` interface.
+//!
+//! [`I2cBus`] issues transfers through an [`I2cBusOps`] backend:
+//! [`DuoI2cDev`] for the real character device and [`FakeI2cBus`] for
+//! running drivers against in-memory devices.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::{GpioError, Result};
+
+pub const I2C_SLAVE: u32 = 0x0703;
+pub const I2C_TENBIT: u32 = 0x0704;
+pub const I2C_FUNCS: u32 = 0x0705;
+pub const I2C_RDWR: u32 = 0x0707;
+pub const I2C_SMBUS: u32 = 0x0720;
+
+pub const I2C_M_RD: u16 = 0x0001;
+pub const I2C_M_TEN: u16 = 0x0010;
+
+pub const I2C_FUNC_I2C: u64 = 0x0000_0001;
+pub const I2C_FUNC_10BIT_ADDR: u64 = 0x0000_0002;
+pub const I2C_FUNC_SMBUS_QUICK: u64 = 0x0001_0000;
+pub const I2C_FUNC_SMBUS_READ_BYTE: u64 = 0x0002_0000;
+
+const I2C_SMBUS_READ: u8 = 1;
+const I2C_SMBUS_WRITE: u8 = 0;
+const I2C_SMBUS_QUICK: u32 = 0;
+const I2C_SMBUS_BYTE: u32 = 1;
+const I2C_SMBUS_BLOCK_MAX: usize = 32;
+
+/// Kernel limits for one `I2C_RDWR` call.
+pub const I2C_RDWR_MAX_MESSAGES: usize = 42;
+pub const I2C_MAX_MESSAGE_LEN: usize = 8192;
+
+/// `struct i2c_msg`
+#[repr(C)]
+#[derive(Debug)]
+pub struct I2cMsg {
+    pub addr: u16,
+    pub flags: u16,
+    pub len: u16,
+    pub buf: *mut u8,
+}
+
+/// `struct i2c_rdwr_ioctl_data`
+#[repr(C)]
+#[derive(Debug)]
+pub struct I2cRdwrIoctlData {
+    pub msgs: *mut I2cMsg,
+    pub nmsgs: u32,
+}
+
+/// `struct i2c_smbus_ioctl_data`, with `union i2c_smbus_data` as its
+/// largest member, the block buffer.
+#[repr(C)]
+#[derive(Debug)]
+pub struct I2cSmbusIoctlData {
+    pub read_write: u8,
+    pub command: u8,
+    pub size: u32,
+    pub data: *mut [u8; I2C_SMBUS_BLOCK_MAX + 2],
+}
+
+/// A target address, validated against its addressing mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum I2cAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+impl I2cAddress {
+    pub fn seven_bit(address: u8) -> Result<Self> {
+        if address <= 0x7f {
+            Ok(Self::SevenBit(address))
+        } else {
+            Err(GpioError::InvalidI2cAddress { address: address.into() })
+        }
+    }
+
+    pub fn ten_bit(address: u16) -> Result<Self> {
+        if address <= 0x3ff {
+            Ok(Self::TenBit(address))
+        } else {
+            Err(GpioError::InvalidI2cAddress { address })
+        }
+    }
+
+    pub fn value(&self) -> u16 {
+        match *self {
+            Self::SevenBit(address) => address.into(),
+            Self::TenBit(address) => address,
+        }
+    }
+
+    pub fn is_ten_bit(&self) -> bool {
+        matches!(self, Self::TenBit(_))
+    }
+}
+
+/// One segment of a combined transfer. Segments are joined with repeated
+/// starts, and the bus is released after the last one.
+#[derive(Debug, PartialEq, Eq)]
+pub enum I2cMessage<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
+impl I2cMessage<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Read(buf) => buf.len(),
+            Self::Write(buf) => buf.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `I2C_M_*` flags of this segment in a transfer to `address`.
+    pub fn flags(&self, address: I2cAddress) -> u16 {
+        let ten_bit = if address.is_ten_bit() { I2C_M_TEN } else { 0 };
+        match self {
+            Self::Read(_) => ten_bit | I2C_M_RD,
+            Self::Write(_) => ten_bit,
+        }
+    }
+}
+
+/// The ioctl layer of an I2C adapter.
+///
+/// [`DuoI2cDev`] talks to `/dev/i2c-N`; tests can substitute
+/// [`FakeI2cBus`] or a mock.
+pub trait I2cBusOps {
+    /// Issues `I2C_FUNCS` and returns the adapter's `I2C_FUNC_*` bits.
+    fn functionality(&self) -> Result<u64>;
+    /// Issues `I2C_RDWR` with `messages` addressed to `address`.
+    fn transfer(&self, address: I2cAddress, messages: &mut [I2cMessage<'_>]) -> Result<()>;
+    /// Issues an SMBus quick write, which only sends the address.
+    fn smbus_quick_write(&self, address: I2cAddress) -> Result<()>;
+    /// Issues an SMBus receive byte.
+    fn smbus_read_byte(&self, address: I2cAddress) -> Result<u8>;
+}
+
+/// A `/dev/i2c-N` device.
+pub struct DuoI2cDev {
+    path: PathBuf,
+    dev: File,
+    // I2C_SLAVE and I2C_TENBIT are per-fd state that the SMBus ioctls
+    // depend on, so selecting and addressing a target must not interleave.
+    smbus: Mutex<()>,
+}
+
+impl DuoI2cDev {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| GpioError::open(&path, e))?;
+
+        Ok(Self { path, dev, smbus: Mutex::new(()) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn ioctl(&self, ioctl: &'static str, request: u32, arg: libc::c_ulong) -> Result<()> {
+        let result = unsafe { libc::ioctl(self.dev.as_raw_fd(), request as _, arg) };
+
+        if result == -1 {
+            Err(GpioError::Ioctl { ioctl, source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn smbus(&self, address: I2cAddress, read_write: u8, size: u32) -> Result<u8> {
+        let _guard = self.smbus.lock().unwrap_or_else(|e| e.into_inner());
+        self.ioctl("I2C_TENBIT", I2C_TENBIT, address.is_ten_bit().into())?;
+        self.ioctl("I2C_SLAVE", I2C_SLAVE, address.value().into())?;
+
+        let mut data = [0u8; I2C_SMBUS_BLOCK_MAX + 2];
+        let mut args = I2cSmbusIoctlData { read_write, command: 0, size, data: &mut data };
+        self.ioctl("I2C_SMBUS", I2C_SMBUS, &mut args as *mut _ as libc::c_ulong)?;
+
+        Ok(data[0])
+    }
+}
+
+impl I2cBusOps for DuoI2cDev {
+    fn functionality(&self) -> Result<u64> {
+        let mut funcs: libc::c_ulong = 0;
+        self.ioctl("I2C_FUNCS", I2C_FUNCS, &mut funcs as *mut _ as libc::c_ulong)?;
+
+        Ok(funcs as u64)
+    }
+
+    fn transfer(&self, address: I2cAddress, messages: &mut [I2cMessage<'_>]) -> Result<()> {
+        let mut msgs: Vec<I2cMsg> = messages
+            .iter_mut()
+            .map(|message| {
+                let len = message.len() as u16;
+                let flags = message.flags(address);
+                let buf = match message {
+                    I2cMessage::Read(buf) => buf.as_mut_ptr(),
+                    // The kernel only reads from write buffers.
+                    I2cMessage::Write(buf) => buf.as_ptr() as *mut u8,
+                };
+                I2cMsg { addr: address.value(), flags, len, buf }
+            })
+            .collect();
+        let mut data = I2cRdwrIoctlData { msgs: msgs.as_mut_ptr(), nmsgs: msgs.len() as u32 };
+
+        self.ioctl("I2C_RDWR", I2C_RDWR, &mut data as *mut _ as libc::c_ulong)
+    }
+
+    fn smbus_quick_write(&self, address: I2cAddress) -> Result<()> {
+        self.smbus(address, I2C_SMBUS_WRITE, I2C_SMBUS_QUICK).map(|_| ())
+    }
+
+    fn smbus_read_byte(&self, address: I2cAddress) -> Result<u8> {
+        self.smbus(address, I2C_SMBUS_READ, I2C_SMBUS_BYTE)
+    }
+}
+
+/// Whether `error` means that no target acknowledged.
+pub fn is_nack(error: &GpioError) -> bool {
+    let errno = error.io_error().and_then(io::Error::raw_os_error);
+    matches!(errno, Some(libc::ENXIO) | Some(libc::EREMOTEIO))
+}
+
+/// An I2C bus master.
+pub struct I2cBus<B: I2cBusOps = DuoI2cDev> {
+    ops: B,
+}
+
+impl I2cBus<DuoI2cDev> {
+    /// Opens `/dev/i2c-{bus}`. The Duo's header exposes I2C0, I2C1 and
+    /// I2C3, see [`Pinmux::set_function_named`] for muxing their pads.
+    ///
+    /// [`Pinmux::set_function_named`]: crate::pinmux::Pinmux::set_function_named
+    pub fn open(bus: u32) -> Result<Self> {
+        Ok(Self::new(DuoI2cDev::open(format!("/dev/i2c-{bus}"))?))
+    }
+}
+
+impl<B: I2cBusOps> I2cBus<B> {
+    pub fn new(ops: B) -> Self {
+        Self { ops }
+    }
+
+    pub fn ops(&self) -> &B {
+        &self.ops
+    }
+
+    pub fn functionality(&self) -> Result<u64> {
+        self.ops.functionality()
+    }
+
+    /// Runs `messages` as one combined transfer.
+    pub fn transaction(&self, address: I2cAddress, messages: &mut [I2cMessage<'_>]) -> Result<()> {
+        let len = messages.iter().map(I2cMessage::len).max().unwrap_or(0);
+        if messages.is_empty()
+            || messages.len() > I2C_RDWR_MAX_MESSAGES
+            || len > I2C_MAX_MESSAGE_LEN
+        {
+            return Err(GpioError::InvalidI2cTransfer { messages: messages.len(), len });
+        }
+        self.ops.transfer(address, messages)
+    }
+
+    pub fn write(&self, address: I2cAddress, data: &[u8]) -> Result<()> {
+        self.transaction(address, &mut [I2cMessage::Write(data)])
+    }
+
+    pub fn read(&self, address: I2cAddress, buf: &mut [u8]) -> Result<()> {
+        self.transaction(address, &mut [I2cMessage::Read(buf)])
+    }
+
+    /// Writes `data`, then reads into `buf` after a repeated start, the
+    /// usual way of reading a register.
+    pub fn write_read(&self, address: I2cAddress, data: &[u8], buf: &mut [u8]) -> Result<()> {
+        self.transaction(address, &mut [I2cMessage::Write(data), I2cMessage::Read(buf)])
+    }
+
+    /// Checks whether a target answers at `address`, the way `i2cdetect`
+    /// does: with a receive byte in the ranges where a quick write could
+    /// corrupt an EEPROM, and a quick write elsewhere.
+    ///
+    /// An address that is busy because a kernel driver has bound to it
+    /// counts as present.
+    pub fn probe(&self, address: I2cAddress) -> Result<bool> {
+        let result = match address.value() {
+            0x30..=0x37 | 0x50..=0x5f => self.ops.smbus_read_byte(address).map(|_| ()),
+            _ => self.ops.smbus_quick_write(address),
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(e) if is_nack(&e) => Ok(false),
+            Err(e) if e.io_error().and_then(io::Error::raw_os_error) == Some(libc::EBUSY) => {
+                Ok(true)
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Probes the 7-bit addresses that are not reserved, 0x08 to 0x77.
+    pub fn scan(&self) -> Result<Vec<I2cAddress>> {
+        let mut found = Vec::new();
+        for address in 0x08..=0x77 {
+            let address = I2cAddress::SevenBit(address);
+            if self.probe(address)? {
+                found.push(address);
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// A device on a [`FakeI2cBus`]: 256 byte-wide registers behind an
+/// auto-incrementing pointer, like most sensors and small EEPROMs. The
+/// first byte of a write sets the pointer, the rest are stored from there.
+#[derive(Clone, Debug)]
+struct FakeDevice {
+    registers: [u8; 256],
+    pointer: u8,
+}
+
+/// An in-memory I2C bus.
+///
+/// Clones share the same devices and transfer log, so a test can keep one
+/// to inspect the registers and the emitted messages after handing another
+/// to [`I2cBus`].
+#[derive(Clone, Default)]
+pub struct FakeI2cBus {
+    devices: Arc<Mutex<HashMap<I2cAddress, FakeDevice>>>,
+    transfers: Arc<Mutex<Vec<FakeTransfer>>>,
+}
+
+/// The `(flags, len)` of each message of one `I2C_RDWR` transfer.
+type FakeTransfer = Vec<(u16, usize)>;
+
+impl FakeI2cBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_device(&self, address: I2cAddress) {
+        let device = FakeDevice { registers: [0; 256], pointer: 0 };
+        self.devices.lock().unwrap().insert(address, device);
+    }
+
+    /// Presets a register without going through the bus.
+    pub fn set(&self, address: I2cAddress, register: u8, val: u8) {
+        if let Some(device) = self.devices.lock().unwrap().get_mut(&address) {
+            device.registers[usize::from(register)] = val;
+        }
+    }
+
+    /// Reads a register without going through the bus.
+    pub fn get(&self, address: I2cAddress, register: u8) -> Option<u8> {
+        let devices = self.devices.lock().unwrap();
+        devices.get(&address).map(|device| device.registers[usize::from(register)])
+    }
+
+    /// The `(flags, len)` of each message of each `I2C_RDWR` transfer so
+    /// far, as the kernel would have received them.
+    pub fn transfers(&self) -> Vec<FakeTransfer> {
+        self.transfers.lock().unwrap().clone()
+    }
+
+    fn nack(ioctl: &'static str) -> GpioError {
+        GpioError::Ioctl { ioctl, source: io::Error::from_raw_os_error(libc::ENXIO) }
+    }
+
+    /// Runs `messages` against the device at `address`, failing if there
+    /// is none.
+    fn run(&self, address: I2cAddress, messages: &mut [I2cMessage<'_>]) -> Result<()> {
+        let mut devices = self.devices.lock().unwrap();
+        let device = devices.get_mut(&address).ok_or_else(|| Self::nack("I2C_RDWR"))?;
+
+        for message in messages {
+            match message {
+                I2cMessage::Write(data) => {
+                    let Some((&pointer, data)) = data.split_first() else { continue };
+                    device.pointer = pointer;
+                    for &byte in data {
+                        device.registers[usize::from(device.pointer)] = byte;
+                        device.pointer = device.pointer.wrapping_add(1);
+                    }
+                },
+                I2cMessage::Read(buf) => {
+                    for byte in buf.iter_mut() {
+                        *byte = device.registers[usize::from(device.pointer)];
+                        device.pointer = device.pointer.wrapping_add(1);
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl I2cBusOps for FakeI2cBus {
+    fn functionality(&self) -> Result<u64> {
+        Ok(I2C_FUNC_I2C | I2C_FUNC_10BIT_ADDR | I2C_FUNC_SMBUS_QUICK | I2C_FUNC_SMBUS_READ_BYTE)
+    }
+
+    fn transfer(&self, address: I2cAddress, messages: &mut [I2cMessage<'_>]) -> Result<()> {
+        let msgs = messages.iter().map(|message| (message.flags(address), message.len()));
+        self.transfers.lock().unwrap().push(msgs.collect());
+
+        self.run(address, messages)
+    }
+
+    fn smbus_quick_write(&self, address: I2cAddress) -> Result<()> {
+        let devices = self.devices.lock().unwrap();
+        devices.get(&address).map(|_| ()).ok_or_else(|| Self::nack("I2C_SMBUS"))
+    }
+
+    fn smbus_read_byte(&self, address: I2cAddress) -> Result<u8> {
+        let mut buf = [0];
+        self.run(address, &mut [I2cMessage::Read(&mut buf)])
+            .map_err(|_| Self::nack("I2C_SMBUS"))?;
+
+        Ok(buf[0])
+    }
+}
@@ -1,20 +1,88 @@
 use std::path::Path;
+use std::time::Duration;
 
-use anyhow::Result;
+use claim::ClaimScope;
+pub use error::{GpioError, Result};
 
+pub mod adc;
+pub mod claim;
 pub mod duo;
+mod error;
+#[cfg(feature = "async")]
+pub mod gpio_async;
+pub mod gpio_cdev;
 pub mod gpio_mmap;
 pub mod gpio_sysfs;
+#[cfg(feature = "embedded-hal")]
+mod hal;
+pub mod i2c;
+pub mod pinmux;
+pub mod port_lock;
+pub mod pwm;
+pub mod serial;
+pub mod sim;
+pub mod spi;
 
 pub trait FileSystemOps {
+    /// Whether this is the real sysfs, so handles claim their line, see
+    /// [`claim`]. Fakes leave this off.
+    const CROSS_PROCESS: bool = false;
+
     fn write(&self, path: &Path, content: &[u8]) -> Result<()>;
     fn read_to_string(&self, path: &Path) -> Result<String>;
+    /// Waits for the sysfs attribute at `path` to signal a change with
+    /// `POLLPRI`/`POLLERR`. Returns `false` if `timeout` elapsed first,
+    /// `None` waits forever.
+    fn poll_priority(&self, path: &Path, timeout: Option<Duration>) -> Result<bool>;
+
+    /// Where handles on this backend claim their line, `None` for no claim.
+    /// The real sysfs claims across the system; fakes don't claim unless
+    /// they override this.
+    fn claim_scope(&self) -> Option<ClaimScope> {
+        Self::CROSS_PROCESS.then_some(ClaimScope::System)
+    }
+}
+
+/// 32-bit register access to a physical address window.
+///
+/// Implemented by [`DevMem`](gpio_mmap::DevMem) and
+/// [`MmioRegion`](gpio_mmap::MmioRegion) for real hardware, and by
+/// [`MemRegisters`](gpio_mmap::MemRegisters) for running driver logic
+/// against plain memory.
+pub trait RegisterIo {
+    /// Whether the registers are shared with other processes, so that
+    /// read-modify-write cycles also take the advisory lock under `/run`
+    /// and handles claim their line, see [`claim`]. In-memory backends
+    /// leave this off.
+    const CROSS_PROCESS: bool = false;
+
+    /// Opens the backend for the physical window `[base, base + len)`.
+    fn map(base: usize, len: usize) -> Result<Self>
+    where
+        Self: Sized;
+    fn read32(&self, addr: usize) -> Result<u32>;
+    fn write32(&self, addr: usize, val: u32) -> Result<()>;
+
+    /// Where handles on these registers claim their line, `None` for no
+    /// claim. Real registers claim across the system; in-memory backends
+    /// claim per instance, so independent fakes don't exclude each other.
+    fn claim_scope(&self) -> Option<ClaimScope> {
+        Self::CROSS_PROCESS.then_some(ClaimScope::System)
+    }
 }
 
+/// Milk-V boards, each with its own header pin table, see [`Device::pin`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Device {
+    /// Milk-V Duo, CV1800B with 64MB.
     Duo,
+    /// Milk-V Duo 256M, SG2002.
+    Duo256M,
+    /// Milk-V Duo S, SG2000.
+    DuoS,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum GpioPort {
     Port0,
     Port1,
@@ -23,11 +91,89 @@ pub enum GpioPort {
     Pwr,
 }
 
+/// A GPIO line that exists on the SoC.
+///
+/// Can only be built from a (port, line) pair listed in the CV1800B pin
+/// tables, see [`GpioPort::valid_lines`], or through [`Pin::on`] from one
+/// that exists on a board's SoC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Pin {
+    port: GpioPort,
+    line: u32,
+}
+
+impl Pin {
+    pub fn new(port: GpioPort, line: u32) -> Result<Self> {
+        Self::on(Device::Duo, port, line)
+    }
+
+    /// Like [`Pin::new`], for a line that exists on `device`, see
+    /// [`Device::valid_lines`].
+    pub fn on(device: Device, port: GpioPort, line: u32) -> Result<Self> {
+        if line < 32 && device.valid_lines(port) & (1 << line) != 0 {
+            Ok(Self { port, line })
+        } else {
+            Err(GpioError::InvalidPin { port, pin: line })
+        }
+    }
+
+    /// Resolves a global sysfs GPIO number, e.g. 440 for XGPIOC[24].
+    pub fn from_sysfs(number: u32) -> Result<Self> {
+        GpioPort::ALL
+            .into_iter()
+            .find(|port| (port.sysfs_base()..port.sysfs_base() + 32).contains(&number))
+            .ok_or(GpioError::UnknownSysfsGpio { number })
+            .and_then(|port| Self::new(port, number - port.sysfs_base()))
+    }
+
+    pub fn port(&self) -> GpioPort {
+        self.port
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn bitmask(&self) -> u32 {
+        1 << self.line
+    }
+
+    pub fn sysfs_number(&self) -> u32 {
+        self.port.sysfs_base() + self.line
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum GpioDirection {
     GpioInput,
     GpioOutput,
 }
 
+/// What a handle does to its line when it is dropped. PWM handles use it
+/// for their channel as well, see [`pwm`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Leave the line exactly as it is, e.g. a relay that must stay
+    /// energized across a service restart. A PWM channel keeps running.
+    LeaveAsIs,
+    /// Make the line an input so it stops driving the pad. A PWM channel
+    /// is stopped.
+    #[default]
+    ResetToInput,
+    /// Put back the line's state from when the handle was opened.
+    Restore,
+}
+
+/// Transitions that wake an edge wait.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Edge {
+    #[default]
+    None,
+    Rising,
+    Falling,
+    Both,
+}
+
 #[derive(Default)]
 pub enum IntLevelType {
     LevelSensitive,
@@ -42,21 +188,133 @@ pub enum IntPolarity {
     ActiveHigh,
 }
 
+/// The clock that samples inputs with debounce enabled.
+///
+/// An input change must be stable for a number of `dbclk` edges before the
+/// block passes it on: pulses shorter than one period are always rejected,
+/// pulses longer than two periods always pass, and anything in between
+/// depends on where the sampling edges fall. With a 1 kHz clock that gives
+/// an effective filter time of 1-2 ms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebounceClock {
+    pub source_hz: u32,
+    pub divider: u32,
+}
+
+impl DebounceClock {
+    pub fn rate_hz(&self) -> u32 {
+        self.source_hz / self.divider.max(1)
+    }
+
+    pub fn period(&self) -> Duration {
+        Duration::from_nanos(1_000_000_000 / u64::from(self.rate_hz().max(1)))
+    }
+
+    /// Longest pulse that is always filtered out.
+    pub fn min_filter_time(&self) -> Duration {
+        self.period()
+    }
+
+    /// Shortest pulse that always gets through.
+    pub fn max_filter_time(&self) -> Duration {
+        self.period() * 2
+    }
+}
+
+/// What makes a line raise its interrupt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Rising,
+    Falling,
+    /// Both edges.
+    Both,
+    High,
+    Low,
+}
+
+impl Trigger {
+    pub fn level_type(&self) -> IntLevelType {
+        match self {
+            Trigger::Rising | Trigger::Falling | Trigger::Both => IntLevelType::EdgeSensitive,
+            Trigger::High | Trigger::Low => IntLevelType::LevelSensitive,
+        }
+    }
+
+    /// Polarity for the single-edge and level triggers. Both-edge
+    /// detection does not use a fixed polarity; this returns active high.
+    pub fn polarity(&self) -> IntPolarity {
+        match self {
+            Trigger::Rising | Trigger::Both | Trigger::High => IntPolarity::ActiveHigh,
+            Trigger::Falling | Trigger::Low => IntPolarity::ActiveLow,
+        }
+    }
+}
+
+/// Only the edge triggers exist in sysfs and the character device.
+impl TryFrom<Trigger> for Edge {
+    type Error = GpioError;
+
+    fn try_from(trigger: Trigger) -> Result<Self> {
+        match trigger {
+            Trigger::Rising => Ok(Edge::Rising),
+            Trigger::Falling => Ok(Edge::Falling),
+            Trigger::Both => Ok(Edge::Both),
+            Trigger::High | Trigger::Low => Err(GpioError::UnsupportedTrigger { trigger }),
+        }
+    }
+}
+
 pub trait Gpio {
     fn new(port: GpioPort, pin: u32) -> Result<Self>
     where
         Self: Sized;
+    /// Opens a pin by board name, e.g. `"GP25"`, `"LED"` or `"XGPIOC[24]"`.
+    fn open(device: Device, name: &str) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        let pin = device.pin(name)?;
+        Self::new(pin.port(), pin.line())
+    }
     fn init(&self, pin_direction: GpioDirection) -> Result<()>;
     fn write_pin(&self, pin_state: bool) -> Result<()>;
+    /// Level on the pad, whichever way the pin is configured.
     fn read_pin(&self) -> Result<u32>;
+    /// Level the pin drives when configured as an output.
+    fn read_output_latch(&self) -> Result<u32>;
+    fn direction(&self) -> Result<GpioDirection>;
     fn enable_interrupt(&self) -> Result<()>;
     fn disable_interrupt(&self) -> Result<()>;
     fn enable_interrupt_mask(&self) -> Result<()>;
     fn disable_interrupt_mask(&self) -> Result<()>;
+    /// Filters the line through the debounce clock, see [`DebounceClock`]
+    /// for the resulting filter time.
     fn enable_debounce(&self) -> Result<()>;
     fn disable_debounce(&self) -> Result<()>;
+    /// The debounce clock as currently programmed in the clock controller.
+    /// It is shared by every line on every port.
+    fn debounce_clock(&self) -> Result<DebounceClock>;
+    /// Programs the debounce clock as close to `hz` as the divider allows
+    /// and returns what was set. This affects every debounced line.
+    fn set_debounce_clock(&self, hz: u32) -> Result<DebounceClock>;
+    /// Synchronises level-sensitive interrupts to `pclk` (`ls_sync`). This
+    /// is a single bit covering the whole port.
+    fn set_level_sync(&self, enable: bool) -> Result<()>;
+    fn level_sync(&self) -> Result<bool>;
     fn set_interrupt_level_type(&self, level_type: IntLevelType) -> Result<()>;
     fn set_interrupt_polarity(&self, polarity: IntPolarity) -> Result<()>;
+    /// Whether the line's interrupt is asserted after masking (`intstatus`).
+    fn interrupt_pending(&self) -> Result<bool>;
+    /// Whether the line's interrupt condition occurred, masked or not
+    /// (`raw_intstatus`).
+    fn raw_interrupt_pending(&self) -> Result<bool>;
+    /// Acknowledges a latched edge interrupt by writing the line's bit to
+    /// `porta_eoi`. Level interrupts clear when the level goes away.
+    fn clear_interrupt(&self) -> Result<()>;
+    /// Programs the line to interrupt on `trigger`. The line stays masked
+    /// while type and polarity change, and edges latched in between are
+    /// cleared before it is unmasked.
+    fn configure_interrupt(&self, trigger: Trigger) -> Result<()>;
     fn enable(&self, addr: usize) -> Result<()>;
     fn disable(&self, addr: usize) -> Result<()>;
 }
@@ -0,0 +1,451 @@
+use crate::gpio_mmap::MmioRegion;
+use crate::{GpioError, GpioPort, Pin, RegisterIo, Result};
+
+/// Base of the CV1800B function mux (FMUX) register block.
+pub const FMUX_BASE: usize = 0x03001000;
+const FMUX_LEN: usize = 0x1000;
+
+/// IOBLK pad control groups. The first three share the page of the FMUX
+/// block, the RTC group sits in the always-on domain with the PWR pads.
+pub const IOBLK_G1: usize = 0x03001800;
+pub const IOBLK_G10: usize = 0x03001a00;
+pub const IOBLK_G12: usize = 0x03001c00;
+pub const IOBLK_RTC: usize = 0x05027000;
+const IOBLK_RTC_LEN: usize = 0x1000;
+
+const PAD_PULL_UP: u32 = 1 << 2;
+const PAD_PULL_DOWN: u32 = 1 << 3;
+const PAD_DRIVE_SHIFT: u32 = 5;
+const PAD_DRIVE_MASK: u32 = 0x7 << PAD_DRIVE_SHIFT;
+const PAD_SCHMITT: u32 = 1 << 8;
+const PAD_SLEW_LIMIT: u32 = 1 << 11;
+const PAD_CONFIG_MASK: u32 =
+    PAD_PULL_UP | PAD_PULL_DOWN | PAD_DRIVE_MASK | PAD_SCHMITT | PAD_SLEW_LIMIT;
+
+/// Function select field of a FMUX register.
+const FUNCSEL_MASK: u32 = 0x7;
+/// Every pad in the table exposes its GPIO line as function 3.
+const GPIO_FUNCTION: u32 = 3;
+
+/// A function a pad can be muxed to. Implemented by one enum per pad.
+pub trait PadFunction: Copy + Sized {
+    const PAD: Pad;
+
+    fn bits(self) -> u32;
+    fn from_bits(bits: u32) -> Option<Self>;
+}
+
+macro_rules! pads {
+    ($(
+        $(#[$doc:meta])*
+        $pad:ident($name:literal, $offset:literal, $ioctrl:expr, $port:ident[$line:literal]) => $function:ident {
+            $($variant:ident = $bits:literal,)+
+        }
+    )+) => {
+        /// Pads that can be muxed to a GPIO line, named after the datasheet.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum Pad {
+            $($(#[$doc])* $pad,)+
+        }
+
+        impl Pad {
+            pub const ALL: &'static [Pad] = &[$(Pad::$pad,)+];
+
+            /// Datasheet name of the pad.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Pad::$pad => $name,)+
+                }
+            }
+
+            /// Physical address of the pad's FMUX register.
+            pub fn fmux_address(&self) -> usize {
+                match self {
+                    $(Pad::$pad => FMUX_BASE + $offset,)+
+                }
+            }
+
+            /// Physical address of the pad's IOBLK pad control register.
+            pub fn ioctrl_address(&self) -> usize {
+                match self {
+                    $(Pad::$pad => $ioctrl,)+
+                }
+            }
+
+            /// The pad's functions by variant name, e.g. `("Pwm5", 7)`.
+            pub fn functions(&self) -> &'static [(&'static str, u32)] {
+                match self {
+                    $(Pad::$pad => &[$((stringify!($variant), $bits),)+],)+
+                }
+            }
+
+            /// GPIO line the pad drives when muxed to its GPIO function.
+            pub fn gpio(&self) -> Pin {
+                let (port, line) = match self {
+                    $(Pad::$pad => (GpioPort::$port, $line),)+
+                };
+                Pin::new(port, line).expect("pad table only lists valid lines")
+            }
+        }
+
+        $(
+            #[doc = concat!("Functions of the ", $name, " pad.")]
+            #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+            pub enum $function {
+                $($variant,)+
+            }
+
+            impl PadFunction for $function {
+                const PAD: Pad = Pad::$pad;
+
+                fn bits(self) -> u32 {
+                    match self {
+                        $($function::$variant => $bits,)+
+                    }
+                }
+
+                fn from_bits(bits: u32) -> Option<Self> {
+                    match bits {
+                        $($bits => Some($function::$variant),)+
+                        _ => None,
+                    }
+                }
+            }
+        )+
+    };
+}
+
+pads! {
+    Sd0PwrEn("SD0_PWR_EN", 0x1c, IOBLK_G10 + 0x1c, Port0[14]) => Sd0PwrEnFunction {
+        Sd0PwrEn = 0,
+        Uart3Rx = 1,
+        Pwm10 = 2,
+        XgpioA14 = 3,
+    }
+    SpkEn("SPK_EN", 0x20, IOBLK_G10 + 0x20, Port0[15]) => SpkEnFunction {
+        SpkEn = 0,
+        Uart3Tx = 1,
+        Pwm11 = 2,
+        XgpioA15 = 3,
+    }
+    Uart0Tx("UART0_TX", 0x24, IOBLK_G10 + 0x24, Port0[16]) => Uart0TxFunction {
+        Uart0Tx = 0,
+        Uart1Tx = 1,
+        Pwm4 = 2,
+        XgpioA16 = 3,
+    }
+    Uart0Rx("UART0_RX", 0x28, IOBLK_G10 + 0x28, Port0[17]) => Uart0RxFunction {
+        Uart0Rx = 0,
+        Uart1Rx = 1,
+        Pwm5 = 2,
+        XgpioA17 = 3,
+    }
+    SpinorHoldX("SPINOR_HOLD_X", 0x2c, IOBLK_G10 + 0x2c, Port0[26]) => SpinorHoldXFunction {
+        SpinorHoldX = 1,
+        XgpioA26 = 3,
+    }
+    SpinorSck("SPINOR_SCK", 0x30, IOBLK_G10 + 0x30, Port0[22]) => SpinorSckFunction {
+        SpinorSck = 1,
+        XgpioA22 = 3,
+    }
+    SpinorMosi("SPINOR_MOSI", 0x34, IOBLK_G10 + 0x34, Port0[25]) => SpinorMosiFunction {
+        SpinorMosi = 1,
+        XgpioA25 = 3,
+    }
+    SpinorWpX("SPINOR_WP_X", 0x38, IOBLK_G10 + 0x38, Port0[27]) => SpinorWpXFunction {
+        SpinorWpX = 1,
+        XgpioA27 = 3,
+    }
+    SpinorMiso("SPINOR_MISO", 0x3c, IOBLK_G10 + 0x3c, Port0[23]) => SpinorMisoFunction {
+        SpinorMiso = 1,
+        XgpioA23 = 3,
+    }
+    SpinorCsX("SPINOR_CS_X", 0x40, IOBLK_G10 + 0x40, Port0[24]) => SpinorCsXFunction {
+        SpinorCsX = 1,
+        XgpioA24 = 3,
+    }
+    Iic0Scl("IIC0_SCL", 0x4c, IOBLK_G10 + 0x4c, Port0[28]) => Iic0SclFunction {
+        JtagTdi = 0,
+        Uart1Tx = 1,
+        Uart2Tx = 2,
+        XgpioA28 = 3,
+        Iic0Scl = 4,
+        Wg0D0 = 5,
+    }
+    Iic0Sda("IIC0_SDA", 0x50, IOBLK_G10 + 0x50, Port0[29]) => Iic0SdaFunction {
+        JtagTdo = 0,
+        Uart1Rx = 1,
+        Uart2Rx = 2,
+        XgpioA29 = 3,
+        Iic0Sda = 4,
+        Wg0D1 = 5,
+    }
+    PwrSeq2("PWR_SEQ2", 0x68, IOBLK_RTC + 0x0c, Pwr[4]) => PwrSeq2Function {
+        PwrSeq2 = 0,
+        PwrGpio4 = 3,
+    }
+    Sd1D3("SD1_D3", 0x8c, IOBLK_RTC + 0x2c, Pwr[18]) => Sd1D3Function {
+        Sd1D3 = 0,
+        Spi2CsX = 1,
+        Iic1Scl = 2,
+        PwrGpio18 = 3,
+        Pwm4 = 7,
+    }
+    Sd1D2("SD1_D2", 0x90, IOBLK_RTC + 0x30, Pwr[19]) => Sd1D2Function {
+        Sd1D2 = 0,
+        Iic1Scl = 1,
+        Uart2Tx = 2,
+        PwrGpio19 = 3,
+        Pwm5 = 7,
+    }
+    Sd1D1("SD1_D1", 0x94, IOBLK_RTC + 0x34, Pwr[20]) => Sd1D1Function {
+        Sd1D1 = 0,
+        Iic1Sda = 1,
+        Uart2Rx = 2,
+        PwrGpio20 = 3,
+        Pwm6 = 7,
+    }
+    Sd1D0("SD1_D0", 0x98, IOBLK_RTC + 0x38, Pwr[21]) => Sd1D0Function {
+        Sd1D0 = 0,
+        Spi2Sdi = 1,
+        Iic1Sda = 2,
+        PwrGpio21 = 3,
+        Pwm7 = 7,
+    }
+    Sd1Cmd("SD1_CMD", 0x9c, IOBLK_RTC + 0x3c, Pwr[22]) => Sd1CmdFunction {
+        Sd1Cmd = 0,
+        Spi2Sdo = 1,
+        Iic3Sda = 2,
+        PwrGpio22 = 3,
+        Pwm8 = 7,
+    }
+    Sd1Clk("SD1_CLK", 0xa0, IOBLK_RTC + 0x40, Pwr[23]) => Sd1ClkFunction {
+        Sd1Clk = 0,
+        Spi2Sck = 1,
+        Iic3Scl = 2,
+        PwrGpio23 = 3,
+        Pwm9 = 7,
+    }
+    Sd1Gpio1("SD1_GPIO1", 0x84, IOBLK_RTC + 0x24, Pwr[26]) => Sd1Gpio1Function {
+        Uart4Tx = 1,
+        PwrGpio26 = 3,
+        Pwm10 = 7,
+    }
+    Sd1Gpio0("SD1_GPIO0", 0x88, IOBLK_RTC + 0x28, Pwr[25]) => Sd1Gpio0Function {
+        Uart4Rx = 1,
+        PwrGpio25 = 3,
+        Pwm11 = 7,
+    }
+    Adc1("ADC1", 0xa8, IOBLK_G1 + 0x04, Port1[3]) => Adc1Function {
+        Adc1 = 0,
+        XgpioB3 = 3,
+        Pwm3 = 4,
+    }
+    UsbVbusDet("USB_VBUS_DET", 0xac, IOBLK_G1 + 0x08, Port1[6]) => UsbVbusDetFunction {
+        UsbVbusDet = 0,
+        XgpioB6 = 3,
+        Pwm4 = 4,
+    }
+    MipiRx1P("PAD_MIPIRX1P", 0xd8, IOBLK_G12 + 0x38, Port2[9]) => MipiRx1PFunction {
+        XgpioC9 = 3,
+        Pwm9 = 4,
+        Iic1Scl = 5,
+    }
+    MipiRx0N("PAD_MIPIRX0N", 0xdc, IOBLK_G12 + 0x3c, Port2[10]) => MipiRx0NFunction {
+        XgpioC10 = 3,
+        Pwm8 = 4,
+        Iic1Sda = 5,
+    }
+}
+
+impl Pad {
+    /// The pad that carries `pin`, if it is one we know the mux for.
+    pub fn for_pin(pin: Pin) -> Option<Pad> {
+        Pad::ALL.iter().copied().find(|pad| pad.gpio() == pin)
+    }
+}
+
+/// The FMUX register block.
+///
+/// Generic over the register backend like
+/// [`MilkVDuoGpio`](crate::duo::MilkVDuoGpio), so it maps `/dev/mem` on the
+/// board and can run against [`MemRegisters`](crate::gpio_mmap::MemRegisters)
+/// in tests.
+pub struct Pinmux<R: RegisterIo = MmioRegion> {
+    regs: R,
+}
+
+impl<R: RegisterIo> Pinmux<R> {
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_registers(R::map(FMUX_BASE, FMUX_LEN)?))
+    }
+
+    pub fn with_registers(regs: R) -> Self {
+        Self { regs }
+    }
+
+    pub fn registers(&self) -> &R {
+        &self.regs
+    }
+
+    /// The raw function select value of `pad`.
+    pub fn raw_function(&self, pad: Pad) -> Result<u32> {
+        Ok(self.regs.read32(pad.fmux_address())? & FUNCSEL_MASK)
+    }
+
+    /// The function `F::PAD` is currently muxed to.
+    pub fn function<F: PadFunction>(&self) -> Result<F> {
+        let value = self.raw_function(F::PAD)?;
+        F::from_bits(value).ok_or(GpioError::UnknownPadFunction { pad: F::PAD.name(), value })
+    }
+
+    pub fn set_function<F: PadFunction>(&self, function: F) -> Result<()> {
+        self.set_raw_function(F::PAD, function.bits())
+    }
+
+    /// Muxes `pad` to the function named like its [`PadFunction`] variant,
+    /// e.g. `"Pwm5"` or `"Iic1Scl"`. Peripheral drivers use this to claim
+    /// the pads they are wired to.
+    pub fn set_function_named(&self, pad: Pad, function: &str) -> Result<()> {
+        let (_, bits) =
+            pad.functions().iter().find(|(name, _)| *name == function).ok_or_else(|| {
+                GpioError::NoPadFunction { pad: pad.name(), function: function.to_string() }
+            })?;
+        self.set_raw_function(pad, *bits)
+    }
+
+    /// Mux the pad carrying `pin` to its GPIO function.
+    pub fn set_gpio(&self, pin: Pin) -> Result<()> {
+        let pad = Pad::for_pin(pin).ok_or(GpioError::NoPadForPin { pin })?;
+        self.set_raw_function(pad, GPIO_FUNCTION)
+    }
+
+    /// Whether the pad carrying `pin` is muxed to its GPIO function.
+    pub fn is_gpio(&self, pin: Pin) -> Result<bool> {
+        let pad = Pad::for_pin(pin).ok_or(GpioError::NoPadForPin { pin })?;
+        Ok(self.raw_function(pad)? == GPIO_FUNCTION)
+    }
+
+    fn set_raw_function(&self, pad: Pad, bits: u32) -> Result<()> {
+        let addr = pad.fmux_address();
+        let value = self.regs.read32(addr)?;
+        self.regs.write32(addr, (value & !FUNCSEL_MASK) | (bits & FUNCSEL_MASK))
+    }
+}
+
+/// Internal pull resistor of a pad.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PadBias {
+    #[default]
+    None,
+    PullUp,
+    PullDown,
+}
+
+/// Electrical configuration of a pad, as held in its IOBLK register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PadConfig {
+    pub bias: PadBias,
+    /// Drive strength level, 0 (weakest) to 7.
+    pub drive_strength: u8,
+    /// Schmitt trigger on the input buffer.
+    pub schmitt: bool,
+    /// Limit the output slew rate.
+    pub slew_limit: bool,
+}
+
+impl Default for PadConfig {
+    fn default() -> Self {
+        Self { bias: PadBias::None, drive_strength: 2, schmitt: true, slew_limit: false }
+    }
+}
+
+impl PadConfig {
+    /// An input with the internal pull-up enabled, e.g. for a button to GND.
+    pub fn pull_up() -> Self {
+        Self { bias: PadBias::PullUp, ..Self::default() }
+    }
+
+    pub fn pull_down() -> Self {
+        Self { bias: PadBias::PullDown, ..Self::default() }
+    }
+
+    fn to_bits(self) -> u32 {
+        let bias = match self.bias {
+            PadBias::None => 0,
+            PadBias::PullUp => PAD_PULL_UP,
+            PadBias::PullDown => PAD_PULL_DOWN,
+        };
+        let drive = (u32::from(self.drive_strength) << PAD_DRIVE_SHIFT) & PAD_DRIVE_MASK;
+        let schmitt = if self.schmitt { PAD_SCHMITT } else { 0 };
+        let slew = if self.slew_limit { PAD_SLEW_LIMIT } else { 0 };
+        bias | drive | schmitt | slew
+    }
+
+    fn from_bits(bits: u32) -> Self {
+        // Both pulls enabled is not a valid setting; report the pull-up.
+        let bias = if bits & PAD_PULL_UP != 0 {
+            PadBias::PullUp
+        } else if bits & PAD_PULL_DOWN != 0 {
+            PadBias::PullDown
+        } else {
+            PadBias::None
+        };
+        Self {
+            bias,
+            drive_strength: ((bits & PAD_DRIVE_MASK) >> PAD_DRIVE_SHIFT) as u8,
+            schmitt: bits & PAD_SCHMITT != 0,
+            slew_limit: bits & PAD_SLEW_LIMIT != 0,
+        }
+    }
+}
+
+/// The IOBLK pad control registers.
+///
+/// PWR pads live in the always-on domain, so two regions are mapped: `core`
+/// for the groups next to the FMUX block and `rtc` for the RTC group.
+pub struct PadControl<R: RegisterIo = MmioRegion> {
+    core: R,
+    rtc: R,
+}
+
+impl<R: RegisterIo> PadControl<R> {
+    pub fn new() -> Result<Self> {
+        Ok(Self::with_registers(R::map(FMUX_BASE, FMUX_LEN)?, R::map(IOBLK_RTC, IOBLK_RTC_LEN)?))
+    }
+
+    pub fn with_registers(core: R, rtc: R) -> Self {
+        Self { core, rtc }
+    }
+
+    fn regs(&self, pad: Pad) -> &R {
+        if pad.ioctrl_address() >= IOBLK_RTC {
+            &self.rtc
+        } else {
+            &self.core
+        }
+    }
+
+    pub fn config(&self, pad: Pad) -> Result<PadConfig> {
+        Ok(PadConfig::from_bits(self.regs(pad).read32(pad.ioctrl_address())?))
+    }
+
+    /// Updates the bias, drive, Schmitt and slew fields of `pad`, leaving the
+    /// other bits of the register alone.
+    pub fn set_config(&self, pad: Pad, config: &PadConfig) -> Result<()> {
+        let regs = self.regs(pad);
+        let addr = pad.ioctrl_address();
+        let value = regs.read32(addr)?;
+        regs.write32(addr, (value & !PAD_CONFIG_MASK) | config.to_bits())
+    }
+
+    /// Configures the pad carrying `pin`.
+    pub fn set_pin_config(&self, pin: Pin, config: &PadConfig) -> Result<()> {
+        let pad = Pad::for_pin(pin).ok_or(GpioError::NoPadForPin { pin })?;
+        self.set_config(pad, config)
+    }
+
+    pub fn set_bias(&self, pad: Pad, bias: PadBias) -> Result<()> {
+        self.set_config(pad, &PadConfig { bias, ..self.config(pad)? })
+    }
+}
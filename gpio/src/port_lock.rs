@@ -0,0 +1,196 @@
+//! Serialises read-modify-write cycles on a GPIO port, a PWM controller or
+//! the clock controller.
+//!
+//! Each of them has an in-process mutex, so handles on different threads
+//! can share one. Backends that touch real registers also take an advisory
+//! `flock(2)` on `/run/duo-gpio/<port>.lock`, `/run/duo-gpio/pwm<N>.lock` or
+//! `/run/duo-gpio/clkgen.lock`, which keeps other processes using this crate
+//! from interleaving with us. The directory can be moved with the
+//! `DUO_GPIO_RUN_DIR` environment variable.
+
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError, TryLockError};
+use std::time::Instant;
+use std::{io, thread};
+
+use crate::{GpioError, GpioPort, Result};
+
+pub const LOCK_DIR: &str = "/run/duo-gpio";
+const LOCK_DIR_ENV: &str = "DUO_GPIO_RUN_DIR";
+/// PWM controllers on the SoC, each driving four channels.
+pub const PWM_CONTROLLERS: usize = 4;
+
+static PORT_MUTEXES: [Mutex<()>; GpioPort::ALL.len()] =
+    [const { Mutex::new(()) }; GpioPort::ALL.len()];
+static LOCK_FILES: [OnceLock<File>; GpioPort::ALL.len()] =
+    [const { OnceLock::new() }; GpioPort::ALL.len()];
+static PWM_MUTEXES: [Mutex<()>; PWM_CONTROLLERS] = [const { Mutex::new(()) }; PWM_CONTROLLERS];
+static PWM_LOCK_FILES: [OnceLock<File>; PWM_CONTROLLERS] =
+    [const { OnceLock::new() }; PWM_CONTROLLERS];
+static CLKGEN_MUTEX: Mutex<()> = Mutex::new(());
+static CLKGEN_LOCK_FILE: OnceLock<File> = OnceLock::new();
+
+/// What a [`PortGuard`] holds.
+#[derive(Clone, Copy, Debug)]
+enum Target {
+    Port(GpioPort),
+    /// A PWM controller, by index.
+    Pwm(usize),
+    Clkgen,
+}
+
+impl Target {
+    fn mutex(self) -> &'static Mutex<()> {
+        match self {
+            Target::Port(port) => &PORT_MUTEXES[port.index()],
+            Target::Pwm(controller) => &PWM_MUTEXES[controller],
+            Target::Clkgen => &CLKGEN_MUTEX,
+        }
+    }
+
+    fn lock_file(self) -> &'static OnceLock<File> {
+        match self {
+            Target::Port(port) => &LOCK_FILES[port.index()],
+            Target::Pwm(controller) => &PWM_LOCK_FILES[controller],
+            Target::Clkgen => &CLKGEN_LOCK_FILE,
+        }
+    }
+
+    fn path(self) -> PathBuf {
+        match self {
+            Target::Port(port) => lock_path(port),
+            Target::Pwm(controller) => run_dir().join(format!("pwm{controller}.lock")),
+            Target::Clkgen => run_dir().join("clkgen.lock"),
+        }
+    }
+}
+
+/// Held while a port or controller is being updated; both locks are
+/// released on drop.
+pub struct PortGuard {
+    target: Target,
+    file: Option<&'static File>,
+    _mutex: MutexGuard<'static, ()>,
+}
+
+/// Directory holding the lock files, [`LOCK_DIR`] unless overridden.
+pub fn run_dir() -> PathBuf {
+    std::env::var_os(LOCK_DIR_ENV).map_or_else(|| PathBuf::from(LOCK_DIR), PathBuf::from)
+}
+
+/// Path of the advisory lock file for `port`.
+pub fn lock_path(port: GpioPort) -> PathBuf {
+    run_dir().join(format!("{port:?}.lock").to_lowercase())
+}
+
+/// Locks `port` for the calling thread and, with `cross_process`, against
+/// other processes. Waiting on either lock is reported through `tracing`.
+pub fn lock_port(port: GpioPort, cross_process: bool) -> Result<PortGuard> {
+    lock(Target::Port(port), cross_process)
+}
+
+/// Like [`lock_port`], for PWM controller `controller`, whose start,
+/// enable, polarity and update registers are shared by its four channels.
+///
+/// # Panics
+///
+/// If `controller` is not below [`PWM_CONTROLLERS`].
+pub fn lock_pwm(controller: usize, cross_process: bool) -> Result<PortGuard> {
+    assert!(controller < PWM_CONTROLLERS, "no PWM controller {controller}");
+    lock(Target::Pwm(controller), cross_process)
+}
+
+/// Like [`lock_port`], for the clock controller, whose divider registers
+/// are shared by every port.
+pub fn lock_clkgen(cross_process: bool) -> Result<PortGuard> {
+    lock(Target::Clkgen, cross_process)
+}
+
+fn lock(target: Target, cross_process: bool) -> Result<PortGuard> {
+    let mutex = target.mutex();
+    let guard = match mutex.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            let start = Instant::now();
+            let guard = mutex.lock().unwrap_or_else(PoisonError::into_inner);
+            tracing::debug!(
+                ?target,
+                thread = ?thread::current().id(),
+                waited_us = start.elapsed().as_micros() as u64,
+                "port lock contended in process"
+            );
+            guard
+        },
+    };
+
+    let file = if cross_process { Some(flock(target)?) } else { None };
+
+    Ok(PortGuard { target, file, _mutex: guard })
+}
+
+fn lock_file(target: Target) -> Result<&'static File> {
+    let cell = target.lock_file();
+    if let Some(file) = cell.get() {
+        return Ok(file);
+    }
+
+    // Only reached with the target's mutex held, so no other thread races
+    // us.
+    let dir = run_dir();
+    fs::create_dir_all(&dir).map_err(|e| GpioError::open(&dir, e))?;
+    let path = target.path();
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| GpioError::open(&path, e))?;
+
+    Ok(cell.get_or_init(|| file))
+}
+
+fn flock(target: Target) -> Result<&'static File> {
+    let file = lock_file(target)?;
+    let fd = file.as_raw_fd();
+
+    if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(file);
+    }
+    let err = io::Error::last_os_error();
+    if err.kind() != io::ErrorKind::WouldBlock {
+        return Err(GpioError::Lock { path: target.path(), source: err });
+    }
+
+    let start = Instant::now();
+    loop {
+        if unsafe { libc::flock(fd, libc::LOCK_EX) } == 0 {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(GpioError::Lock { path: target.path(), source: err });
+        }
+    }
+    tracing::debug!(
+        ?target,
+        waited_us = start.elapsed().as_micros() as u64,
+        "port lock contended by another process"
+    );
+
+    Ok(file)
+}
+
+impl Drop for PortGuard {
+    fn drop(&mut self) {
+        if let Some(file) = self.file {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+                let err = io::Error::last_os_error();
+                log::error!("Error trying to unlock {:?}: {err}", self.target);
+            }
+        }
+    }
+}
@@ -0,0 +1,429 @@
+//! PWM outputs of the CV1800B.
+//!
+//! The SoC has four PWM controllers with four channels each, PWM0 to PWM15.
+//! A channel can be driven through the kernel's `/sys/class/pwm` interface
+//! with [`PwmSysfs`], or straight through the controller registers with
+//! [`PwmRegisters`]. Both implement [`Pwm`].
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::gpio_mmap::MmioRegion;
+use crate::pinmux::Pinmux;
+use crate::port_lock::{self, PortGuard};
+use crate::{Device, DropPolicy, FileSystemOps, GpioError, RegisterIo, Result};
+
+pub const PWM0_BASE: usize = 0x03060000;
+const PWM_CONTROLLER_STRIDE: usize = 0x1000;
+const PWM_LEN: usize = 0x100;
+/// The controllers count periods of the 100 MHz bus clock.
+pub const PWM_CLOCK_HZ: u64 = 100_000_000;
+const NANOS_PER_CYCLE: u64 = 1_000_000_000 / PWM_CLOCK_HZ;
+const MIN_PERIOD_CYCLES: u64 = 2;
+const MAX_PERIOD_CYCLES: u64 = (1 << 30) - 1;
+
+const HLPERIOD: usize = 0x00;
+const PERIOD: usize = 0x04;
+const CHANNEL_STRIDE: usize = 0x08;
+const POLARITY: usize = 0x40;
+const PWMSTART: usize = 0x44;
+const PWMUPDATE: usize = 0x4c;
+const PWM_OE: usize = 0xd0;
+
+const PWM_PATH: &str = "/sys/class/pwm";
+const EXPORT: &str = "export";
+const UNEXPORT: &str = "unexport";
+const PERIOD_ATTR: &str = "period";
+const DUTY_CYCLE: &str = "duty_cycle";
+const POLARITY_ATTR: &str = "polarity";
+const ENABLE: &str = "enable";
+
+/// One of the sixteen PWM outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PwmChannel(u32);
+
+impl PwmChannel {
+    pub fn new(channel: u32) -> Result<Self> {
+        if channel < 16 {
+            Ok(Self(channel))
+        } else {
+            Err(GpioError::InvalidPwmChannel { channel })
+        }
+    }
+
+    /// Global channel number, the N in PWMN.
+    pub fn number(&self) -> u32 {
+        self.0
+    }
+
+    pub fn controller(&self) -> u32 {
+        self.0 / 4
+    }
+
+    /// Channel within its controller.
+    pub fn index(&self) -> u32 {
+        self.0 % 4
+    }
+
+    pub fn base_address(&self) -> usize {
+        PWM0_BASE + self.controller() as usize * PWM_CONTROLLER_STRIDE
+    }
+
+    /// The kernel numbers each controller's pwmchip after its first channel.
+    pub fn sysfs_chip(&self) -> u32 {
+        self.controller() * 4
+    }
+
+    /// Muxes the header pin `name` to this channel, e.g. `GP4` to PWM5 on
+    /// the Duo.
+    pub fn mux_pin<R: RegisterIo>(
+        &self,
+        pinmux: &Pinmux<R>,
+        device: Device,
+        name: &str,
+    ) -> Result<()> {
+        let pad = device.pad(name)?;
+        pinmux.set_function_named(pad, &format!("Pwm{}", self.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PwmPolarity {
+    /// High for the duty cycle, low for the rest of the period.
+    #[default]
+    Normal,
+    Inversed,
+}
+
+pub trait Pwm {
+    fn period(&self) -> Result<Duration>;
+    /// Sets the period, shortening the duty cycle if it no longer fits.
+    fn set_period(&self, period: Duration) -> Result<()>;
+    fn duty_cycle(&self) -> Result<Duration>;
+    /// Sets the active time within each period, at most the period.
+    fn set_duty_cycle(&self, duty: Duration) -> Result<()>;
+    fn set_polarity(&self, polarity: PwmPolarity) -> Result<()>;
+    fn enable(&self) -> Result<()>;
+    fn disable(&self) -> Result<()>;
+
+    fn frequency(&self) -> Result<f64> {
+        Ok(1.0 / self.period()?.as_secs_f64())
+    }
+
+    fn set_frequency(&self, hz: f64) -> Result<()> {
+        if !(hz > 0.0 && hz.is_finite()) {
+            return Err(GpioError::InvalidPwmPeriod { period: Duration::ZERO });
+        }
+        self.set_period(Duration::from_secs_f64(1.0 / hz))
+    }
+
+    /// Duty cycle as a fraction of the period, 0.0 to 1.0.
+    fn duty_fraction(&self) -> Result<f64> {
+        Ok(self.duty_cycle()?.as_secs_f64() / self.period()?.as_secs_f64())
+    }
+
+    fn set_duty_fraction(&self, fraction: f64) -> Result<()> {
+        let period = self.period()?;
+        self.set_duty_cycle(period.mul_f64(fraction.clamp(0.0, 1.0)))
+    }
+}
+
+/// Rounds to the nearest cycle, so fractions of the period that land just
+/// short of a cycle boundary are not truncated.
+fn cycles(duration: Duration) -> u64 {
+    let nanos = duration.as_nanos() + u128::from(NANOS_PER_CYCLE / 2);
+    (nanos / u128::from(NANOS_PER_CYCLE)).min(u128::from(u64::MAX)) as u64
+}
+
+fn duration(cycles: u64) -> Duration {
+    Duration::from_nanos(cycles * NANOS_PER_CYCLE)
+}
+
+/// A PWM channel driven through the controller registers.
+///
+/// `HLPERIOD` holds the number of cycles the output spends in its inactive
+/// level, so the duty cycle is `PERIOD - HLPERIOD`.
+pub struct PwmRegisters<R: RegisterIo = MmioRegion> {
+    channel: PwmChannel,
+    regs: R,
+    drop_policy: DropPolicy,
+    /// The channel was running when the handle was opened, `None` if that
+    /// could not be read.
+    was_running: Option<bool>,
+}
+
+impl<R: RegisterIo> PwmRegisters<R> {
+    pub fn new(channel: PwmChannel) -> Result<Self> {
+        let regs = R::map(channel.base_address(), PWM_LEN)?;
+        Ok(Self::with_registers(channel, regs))
+    }
+
+    pub fn with_registers(channel: PwmChannel, regs: R) -> Self {
+        let start = regs.read32(channel.base_address() + PWMSTART);
+        let was_running = start.ok().map(|start| start & 1 << channel.index() != 0);
+        Self { channel, regs, drop_policy: DropPolicy::default(), was_running }
+    }
+
+    /// Sets what happens to the channel on drop. [`DropPolicy::LeaveAsIs`]
+    /// keeps it running, [`DropPolicy::ResetToInput`] stops it and
+    /// [`DropPolicy::Restore`] stops it unless it was running when the
+    /// handle was opened.
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    pub fn channel(&self) -> PwmChannel {
+        self.channel
+    }
+
+    pub fn registers(&self) -> &R {
+        &self.regs
+    }
+
+    fn addr(&self, offset: usize) -> usize {
+        self.channel.base_address() + offset
+    }
+
+    fn channel_addr(&self, offset: usize) -> usize {
+        self.addr(offset + self.channel.index() as usize * CHANNEL_STRIDE)
+    }
+
+    fn bit(&self) -> u32 {
+        1 << self.channel.index()
+    }
+
+    fn lock(&self) -> Result<PortGuard> {
+        port_lock::lock_pwm(self.channel.controller() as usize, R::CROSS_PROCESS)
+    }
+
+    /// Read-modify-write of a register shared by the controller's channels;
+    /// the caller holds [`lock`](Self::lock).
+    fn modify(&self, offset: usize, f: impl FnOnce(u32) -> u32) -> Result<()> {
+        let addr = self.addr(offset);
+        let val = self.regs.read32(addr)?;
+        self.regs.write32(addr, f(val))
+    }
+
+    fn write_cycles(&self, period: u64, duty: u64) -> Result<()> {
+        self.regs.write32(self.channel_addr(PERIOD), period as u32)?;
+        self.regs.write32(self.channel_addr(HLPERIOD), (period - duty) as u32)?;
+        // A running channel only picks up new values on an update pulse.
+        let _lock = self.lock()?;
+        self.modify(PWMUPDATE, |v| v | self.bit())?;
+        self.modify(PWMUPDATE, |v| v & !self.bit())
+    }
+
+    fn period_cycles(&self) -> Result<u64> {
+        Ok(u64::from(self.regs.read32(self.channel_addr(PERIOD))?))
+    }
+
+    fn duty_cycles(&self) -> Result<u64> {
+        let period = self.period_cycles()?;
+        let hlperiod = u64::from(self.regs.read32(self.channel_addr(HLPERIOD))?);
+        Ok(period.saturating_sub(hlperiod))
+    }
+}
+
+impl<R: RegisterIo> Pwm for PwmRegisters<R> {
+    fn period(&self) -> Result<Duration> {
+        Ok(duration(self.period_cycles()?))
+    }
+
+    fn set_period(&self, period: Duration) -> Result<()> {
+        let period_cycles = cycles(period);
+        if !(MIN_PERIOD_CYCLES..=MAX_PERIOD_CYCLES).contains(&period_cycles) {
+            return Err(GpioError::InvalidPwmPeriod { period });
+        }
+        let duty = self.duty_cycles()?.min(period_cycles);
+        self.write_cycles(period_cycles, duty)
+    }
+
+    fn duty_cycle(&self) -> Result<Duration> {
+        Ok(duration(self.duty_cycles()?))
+    }
+
+    fn set_duty_cycle(&self, duty: Duration) -> Result<()> {
+        let period = self.period_cycles()?;
+        let duty_cycles = cycles(duty);
+        if duty_cycles > period {
+            return Err(GpioError::InvalidPwmDuty { duty, period: duration(period) });
+        }
+        self.write_cycles(period, duty_cycles)
+    }
+
+    fn set_polarity(&self, polarity: PwmPolarity) -> Result<()> {
+        let _lock = self.lock()?;
+        self.modify(POLARITY, |v| match polarity {
+            PwmPolarity::Normal => v & !self.bit(),
+            PwmPolarity::Inversed => v | self.bit(),
+        })
+    }
+
+    fn enable(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        self.modify(PWM_OE, |v| v | self.bit())?;
+        self.modify(PWMSTART, |v| v | self.bit())
+    }
+
+    fn disable(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        self.modify(PWMSTART, |v| v & !self.bit())?;
+        self.modify(PWM_OE, |v| v & !self.bit())
+    }
+}
+
+impl<R: RegisterIo> Drop for PwmRegisters<R> {
+    fn drop(&mut self) {
+        let stop = match self.drop_policy {
+            DropPolicy::LeaveAsIs => false,
+            DropPolicy::ResetToInput => true,
+            DropPolicy::Restore => self.was_running != Some(true),
+        };
+        if stop {
+            if let Err(e) = self.disable() {
+                log::error!("Error trying to stop PWM{}: {e}", self.channel.number());
+            }
+        }
+    }
+}
+
+/// A PWM channel exported through `/sys/class/pwm`.
+pub struct PwmSysfs<F: FileSystemOps> {
+    channel: PwmChannel,
+    chip_path: PathBuf,
+    pwm_path: PathBuf,
+    fs_ops: F,
+    drop_policy: DropPolicy,
+    /// The channel was already exported when the handle was opened.
+    adopted: bool,
+}
+
+impl<F: FileSystemOps> PwmSysfs<F> {
+    /// Exports `channel` from its pwmchip.
+    ///
+    /// A channel that is still exported, e.g. by a previous run that
+    /// dropped its handle with [`DropPolicy::LeaveAsIs`], is adopted as it
+    /// is, like [`GpioSysfs`](crate::gpio_sysfs::GpioSysfs) does for lines.
+    pub fn new(channel: PwmChannel, fs_ops: F) -> Result<Self> {
+        let chip_path = Path::new(PWM_PATH).join(format!("pwmchip{}", channel.sysfs_chip()));
+        let pwm_path = chip_path.join(format!("pwm{}", channel.index()));
+        let adopted = match fs_ops
+            .write(&chip_path.join(EXPORT), channel.index().to_string().as_bytes())
+        {
+            Ok(()) => false,
+            // The kernel answers EBUSY when the channel is already exported.
+            Err(GpioError::Sysfs { source, .. }) if source.raw_os_error() == Some(libc::EBUSY) => {
+                true
+            },
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            channel,
+            chip_path,
+            pwm_path,
+            fs_ops,
+            drop_policy: DropPolicy::default(),
+            adopted,
+        })
+    }
+
+    /// Sets what happens to the channel on drop. [`DropPolicy::LeaveAsIs`]
+    /// keeps it running and exported, [`DropPolicy::ResetToInput`] stops it
+    /// and [`DropPolicy::Restore`] puts the export back as it was when the
+    /// handle was opened. Only a channel this handle exported is unexported.
+    pub fn with_drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    pub fn channel(&self) -> PwmChannel {
+        self.channel
+    }
+
+    /// Whether the channel was already exported when the handle was opened.
+    pub fn is_adopted(&self) -> bool {
+        self.adopted
+    }
+
+    fn write_attr(&self, attr: &str, value: impl ToString) -> Result<()> {
+        self.fs_ops.write(&self.pwm_path.join(attr), value.to_string().as_bytes())
+    }
+
+    fn read_nanos(&self, attr: &str) -> Result<u64> {
+        let path = self.pwm_path.join(attr);
+        let value = self.fs_ops.read_to_string(&path)?;
+        value.trim().parse().map_err(|_| GpioError::InvalidValue { path, value })
+    }
+
+    pub fn unexport(&self) -> Result<()> {
+        self.fs_ops
+            .write(&self.chip_path.join(UNEXPORT), self.channel.index().to_string().as_bytes())
+    }
+}
+
+impl<F: FileSystemOps> Pwm for PwmSysfs<F> {
+    fn period(&self) -> Result<Duration> {
+        Ok(Duration::from_nanos(self.read_nanos(PERIOD_ATTR)?))
+    }
+
+    fn set_period(&self, period: Duration) -> Result<()> {
+        let nanos = period.as_nanos() as u64;
+        if nanos == 0 {
+            return Err(GpioError::InvalidPwmPeriod { period });
+        }
+        // The kernel rejects a period shorter than the current duty cycle.
+        if self.read_nanos(DUTY_CYCLE)? > nanos {
+            self.write_attr(DUTY_CYCLE, nanos)?;
+        }
+        self.write_attr(PERIOD_ATTR, nanos)
+    }
+
+    fn duty_cycle(&self) -> Result<Duration> {
+        Ok(Duration::from_nanos(self.read_nanos(DUTY_CYCLE)?))
+    }
+
+    fn set_duty_cycle(&self, duty: Duration) -> Result<()> {
+        let period = self.period()?;
+        if duty > period {
+            return Err(GpioError::InvalidPwmDuty { duty, period });
+        }
+        self.write_attr(DUTY_CYCLE, duty.as_nanos())
+    }
+
+    fn set_polarity(&self, polarity: PwmPolarity) -> Result<()> {
+        let value = match polarity {
+            PwmPolarity::Normal => "normal",
+            PwmPolarity::Inversed => "inversed",
+        };
+        self.write_attr(POLARITY_ATTR, value)
+    }
+
+    fn enable(&self) -> Result<()> {
+        self.write_attr(ENABLE, 1)
+    }
+
+    fn disable(&self) -> Result<()> {
+        self.write_attr(ENABLE, 0)
+    }
+}
+
+impl<F: FileSystemOps> Drop for PwmSysfs<F> {
+    fn drop(&mut self) {
+        if self.drop_policy == DropPolicy::LeaveAsIs
+            || (self.drop_policy == DropPolicy::Restore && self.adopted)
+        {
+            return;
+        }
+        if let Err(e) = self.disable() {
+            log::error!("Error trying to disable PWM{}: {e}", self.channel.number());
+        }
+        if self.adopted {
+            return;
+        }
+        if let Err(e) = self.unexport() {
+            log::error!("Error trying to unexport PWM{}: {e}", self.channel.number());
+        }
+    }
+}
@@ -0,0 +1,386 @@
+//! Serial ports on `/dev/ttyS*`.
+//!
+//! [`SerialPort`] puts the tty in raw mode and configures its framing with
+//! `termios2`, which takes any baud rate the UART clock can divide down to.
+//! RS-485 transceivers can have their driver enable toggled around each
+//! write from a [`Gpio`] pin, or left to the kernel with `TIOCSRS485`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::duo::MilkVDuoGpio;
+use crate::pinmux::Pinmux;
+use crate::{Device, Gpio, GpioDirection, GpioError, RegisterIo, Result};
+
+const SER_RS485_ENABLED: u32 = 1 << 0;
+const SER_RS485_RTS_ON_SEND: u32 = 1 << 1;
+const SER_RS485_RTS_AFTER_SEND: u32 = 1 << 2;
+const SER_RS485_RX_DURING_TX: u32 = 1 << 4;
+
+/// `struct serial_rs485`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SerialRs485 {
+    pub flags: u32,
+    pub delay_rts_before_send: u32,
+    pub delay_rts_after_send: u32,
+    pub padding: [u32; 5],
+}
+
+/// One of the CV1800B's five UARTs. UART0 carries the Duo's console.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Uart(u32);
+
+impl Uart {
+    pub fn new(uart: u32) -> Result<Self> {
+        if uart < 5 {
+            Ok(Self(uart))
+        } else {
+            Err(GpioError::InvalidUart { uart })
+        }
+    }
+
+    pub fn number(&self) -> u32 {
+        self.0
+    }
+
+    pub fn device_path(&self) -> PathBuf {
+        PathBuf::from(format!("/dev/ttyS{}", self.0))
+    }
+
+    /// Muxes the header pins `tx` and `rx` to this UART, e.g. `GP0` and
+    /// `GP1` to UART1 on the Duo.
+    pub fn mux_pins<R: RegisterIo>(
+        &self,
+        pinmux: &Pinmux<R>,
+        device: Device,
+        tx: &str,
+        rx: &str,
+    ) -> Result<()> {
+        pinmux.set_function_named(device.pad(tx)?, &format!("Uart{}Tx", self.0))?;
+        pinmux.set_function_named(device.pad(rx)?, &format!("Uart{}Rx", self.0))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    #[default]
+    Eight,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Parity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StopBits {
+    #[default]
+    One,
+    Two,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlowControl {
+    #[default]
+    None,
+    /// RTS/CTS.
+    Hardware,
+    /// XON/XOFF.
+    Software,
+}
+
+/// Line settings. `vmin` and `vtime` have their termios meaning: a read
+/// returns once `vmin` bytes arrived, or `vtime` tenths of a second after
+/// the last byte. With `vmin` at 0, `vtime` is a plain read timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub vmin: u8,
+    pub vtime: u8,
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            baud_rate: 115_200,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            vmin: 1,
+            vtime: 0,
+        }
+    }
+}
+
+impl SerialConfig {
+    pub fn new(baud_rate: u32) -> Self {
+        Self { baud_rate, ..Self::default() }
+    }
+
+    fn apply(&self, tio: &mut libc::termios2) {
+        // cfmakeraw
+        tio.c_iflag &= !(libc::IGNBRK
+            | libc::BRKINT
+            | libc::PARMRK
+            | libc::ISTRIP
+            | libc::INLCR
+            | libc::IGNCR
+            | libc::ICRNL
+            | libc::IXON
+            | libc::IXOFF
+            | libc::IXANY
+            | libc::INPCK);
+        tio.c_oflag &= !libc::OPOST;
+        tio.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        tio.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::PARODD | libc::CSTOPB | libc::CRTSCTS);
+        tio.c_cflag |= libc::CREAD | libc::CLOCAL;
+
+        tio.c_cflag |= match self.data_bits {
+            DataBits::Five => libc::CS5,
+            DataBits::Six => libc::CS6,
+            DataBits::Seven => libc::CS7,
+            DataBits::Eight => libc::CS8,
+        };
+        match self.parity {
+            Parity::None => {},
+            Parity::Even => {
+                tio.c_cflag |= libc::PARENB;
+                tio.c_iflag |= libc::INPCK;
+            },
+            Parity::Odd => {
+                tio.c_cflag |= libc::PARENB | libc::PARODD;
+                tio.c_iflag |= libc::INPCK;
+            },
+        }
+        if self.stop_bits == StopBits::Two {
+            tio.c_cflag |= libc::CSTOPB;
+        }
+        match self.flow_control {
+            FlowControl::None => {},
+            FlowControl::Hardware => tio.c_cflag |= libc::CRTSCTS,
+            FlowControl::Software => tio.c_iflag |= libc::IXON | libc::IXOFF,
+        }
+
+        // BOTHER takes the rate from c_ispeed and c_ospeed as is.
+        tio.c_cflag &= !(libc::CBAUD | (libc::CBAUD << libc::IBSHIFT));
+        tio.c_cflag |= libc::BOTHER | (libc::BOTHER << libc::IBSHIFT);
+        tio.c_ispeed = self.baud_rate;
+        tio.c_ospeed = self.baud_rate;
+
+        tio.c_cc[libc::VMIN] = self.vmin;
+        tio.c_cc[libc::VTIME] = self.vtime;
+    }
+
+    fn from_termios(tio: &libc::termios2) -> Self {
+        let data_bits = match tio.c_cflag & libc::CSIZE {
+            libc::CS5 => DataBits::Five,
+            libc::CS6 => DataBits::Six,
+            libc::CS7 => DataBits::Seven,
+            _ => DataBits::Eight,
+        };
+        let parity = match (tio.c_cflag & libc::PARENB != 0, tio.c_cflag & libc::PARODD != 0) {
+            (false, _) => Parity::None,
+            (true, false) => Parity::Even,
+            (true, true) => Parity::Odd,
+        };
+        let stop_bits = if tio.c_cflag & libc::CSTOPB != 0 { StopBits::Two } else { StopBits::One };
+        let flow_control = if tio.c_cflag & libc::CRTSCTS != 0 {
+            FlowControl::Hardware
+        } else if tio.c_iflag & libc::IXON != 0 {
+            FlowControl::Software
+        } else {
+            FlowControl::None
+        };
+
+        Self {
+            baud_rate: tio.c_ospeed,
+            data_bits,
+            parity,
+            stop_bits,
+            flow_control,
+            vmin: tio.c_cc[libc::VMIN],
+            vtime: tio.c_cc[libc::VTIME],
+        }
+    }
+}
+
+/// Kernel RS-485 mode, where the UART driver raises RTS as the
+/// transceiver's driver enable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rs485Config {
+    /// RTS level while sending; it takes the opposite level afterwards.
+    pub rts_on_send: bool,
+    /// Keep receiving while sending, to read back the own transmission.
+    pub rx_during_tx: bool,
+    pub delay_before_send_ms: u32,
+    pub delay_after_send_ms: u32,
+}
+
+impl Rs485Config {
+    fn to_raw(self) -> SerialRs485 {
+        let mut flags = SER_RS485_ENABLED;
+        flags |= if self.rts_on_send { SER_RS485_RTS_ON_SEND } else { SER_RS485_RTS_AFTER_SEND };
+        if self.rx_during_tx {
+            flags |= SER_RS485_RX_DURING_TX;
+        }
+        SerialRs485 {
+            flags,
+            delay_rts_before_send: self.delay_before_send_ms,
+            delay_rts_after_send: self.delay_after_send_ms,
+            ..Default::default()
+        }
+    }
+}
+
+/// A transceiver's driver enable, asserted while a write drains.
+struct DirectionPin<G> {
+    pin: G,
+    active_high: bool,
+}
+
+/// A tty in raw mode.
+pub struct SerialPort<G: Gpio = MilkVDuoGpio<'static>> {
+    path: PathBuf,
+    tty: File,
+    direction: Option<DirectionPin<G>>,
+}
+
+impl SerialPort {
+    pub fn open(path: impl AsRef<Path>, config: &SerialConfig) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&path)
+            .map_err(|e| GpioError::open(&path, e))?;
+        let port = Self { path, tty, direction: None };
+        port.configure(config)?;
+
+        Ok(port)
+    }
+
+    pub fn for_uart(uart: Uart, config: &SerialConfig) -> Result<Self> {
+        Self::open(uart.device_path(), config)
+    }
+}
+
+impl<G: Gpio> SerialPort<G> {
+    /// Drives `pin` as an RS-485 driver enable: asserted for each write
+    /// until the last byte has left the UART, and inactive otherwise.
+    pub fn with_direction_pin<H: Gpio>(self, pin: H, active_high: bool) -> Result<SerialPort<H>> {
+        pin.write_pin(!active_high)?;
+        pin.init(GpioDirection::GpioOutput)?;
+
+        Ok(SerialPort {
+            path: self.path,
+            tty: self.tty,
+            direction: Some(DirectionPin { pin, active_high }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn direction_pin(&self) -> Option<&G> {
+        self.direction.as_ref().map(|direction| &direction.pin)
+    }
+
+    fn ioctl<T>(&self, ioctl: &'static str, request: libc::Ioctl, arg: *mut T) -> Result<()> {
+        let result = unsafe { libc::ioctl(self.tty.as_raw_fd(), request, arg) };
+
+        if result == -1 {
+            Err(GpioError::Ioctl { ioctl, source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn termios(&self) -> Result<libc::termios2> {
+        let mut tio: libc::termios2 = unsafe { std::mem::zeroed() };
+        self.ioctl("TCGETS2", libc::TCGETS2, &mut tio)?;
+
+        Ok(tio)
+    }
+
+    pub fn configure(&self, config: &SerialConfig) -> Result<()> {
+        if config.baud_rate == 0 {
+            return Err(GpioError::InvalidBaudRate { baud_rate: 0 });
+        }
+        let mut tio = self.termios()?;
+        config.apply(&mut tio);
+        self.ioctl("TCSETS2", libc::TCSETS2, &mut tio)
+    }
+
+    /// Reads the settings back from the tty, with the baud rate the driver
+    /// actually chose.
+    pub fn config(&self) -> Result<SerialConfig> {
+        Ok(SerialConfig::from_termios(&self.termios()?))
+    }
+
+    /// Enables kernel RS-485 mode, or disables it with `None`. Fails with
+    /// `ENOTTY` on UARTs whose driver lacks RS-485 support.
+    pub fn set_kernel_rs485(&self, config: Option<&Rs485Config>) -> Result<()> {
+        let mut raw = config.map(|config| config.to_raw()).unwrap_or_default();
+        self.ioctl("TIOCSRS485", libc::TIOCSRS485, &mut raw)
+    }
+
+    /// Waits until everything written has been transmitted.
+    pub fn drain(&self) -> Result<()> {
+        if unsafe { libc::tcdrain(self.tty.as_raw_fd()) } == -1 {
+            Err(GpioError::Ioctl { ioctl: "tcdrain", source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Drops received bytes that have not been read yet.
+    pub fn discard_input(&self) -> Result<()> {
+        if unsafe { libc::tcflush(self.tty.as_raw_fd(), libc::TCIFLUSH) } == -1 {
+            Err(GpioError::Ioctl { ioctl: "tcflush", source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<G: Gpio> Read for SerialPort<G> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tty.read(buf)
+    }
+}
+
+impl<G: Gpio> Write for SerialPort<G> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(direction) = &self.direction else {
+            return self.tty.write(buf);
+        };
+        direction.pin.write_pin(direction.active_high).map_err(io::Error::other)?;
+        let result =
+            (&self.tty).write_all(buf).and_then(|()| self.drain().map_err(io::Error::other));
+        // Release the bus even if the write failed.
+        let released = direction.pin.write_pin(!direction.active_high).map_err(io::Error::other);
+
+        result.and(released).map(|()| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tty.flush()
+    }
+}
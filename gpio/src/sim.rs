@@ -0,0 +1,211 @@
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+
+use crate::claim::ClaimScope;
+use crate::duo::DuoGpio;
+use crate::{GpioError, GpioPort, RegisterIo, Result};
+
+#[derive(Debug, Default)]
+struct SimState {
+    swporta_dr: u32,
+    swporta_ddr: u32,
+    inten: u32,
+    intmask: u32,
+    inttype_level: u32,
+    int_polarity: u32,
+    debounce: u32,
+    ls_sync: u32,
+    int_bothedge: u32,
+    /// Whether the modelled block has `int_bothedge`; without it the
+    /// register reads as zero and ignores writes.
+    has_bothedge: bool,
+    /// Levels driven onto the pads from outside the chip.
+    external: u32,
+    /// Latched edge interrupts, cleared through `porta_eoi`.
+    edge_status: u32,
+}
+
+impl SimState {
+    /// The pad levels as seen through `ext_porta`: outputs reflect the data
+    /// register, inputs reflect whatever is driven externally.
+    fn ext_porta(&self) -> u32 {
+        (self.swporta_dr & self.swporta_ddr) | (self.external & !self.swporta_ddr)
+    }
+
+    /// Level interrupts follow the pad for as long as the level is asserted.
+    fn level_status(&self) -> u32 {
+        let asserted = !(self.ext_porta() ^ self.int_polarity);
+        asserted & !self.inttype_level & !self.swporta_ddr
+    }
+
+    fn raw_intstatus(&self) -> u32 {
+        (self.edge_status | self.level_status()) & self.inten
+    }
+
+    fn intstatus(&self) -> u32 {
+        self.raw_intstatus() & !self.intmask
+    }
+
+    /// Runs `update` and latches any edge it produced on an input line.
+    fn update(&mut self, update: impl FnOnce(&mut Self)) {
+        let before = self.ext_porta();
+        update(self);
+        let after = self.ext_porta();
+
+        let rising = !before & after & (self.int_polarity | self.int_bothedge);
+        let falling = before & !after & (!self.int_polarity | self.int_bothedge);
+        let edge_lines = self.inten & self.inttype_level & !self.swporta_ddr;
+
+        self.edge_status |= (rising | falling) & edge_lines;
+    }
+}
+
+/// Behavioural model of the Synopsys DesignWare APB GPIO block, as laid out
+/// by [`DuoGpio`].
+///
+/// The simulator decodes the same physical addresses the drivers use, so it
+/// can be handed to [`MilkVDuoGpio::with_registers`] in place of
+/// [`MmioRegion`]. Tests drive input pads with [`drive_input`] and observe
+/// outputs and interrupt state, the way a board would.
+///
+/// The `int_bothedge` register is absent unless enabled with
+/// [`with_bothedge`](DwApbGpioSim::with_bothedge), like on older
+/// configurations of the block.
+///
+/// Debounce and `ls_sync` are stored but have no timing effect: every input
+/// change is seen immediately.
+///
+/// [`MilkVDuoGpio::with_registers`]: crate::duo::MilkVDuoGpio::with_registers
+/// [`MmioRegion`]: crate::gpio_mmap::MmioRegion
+/// [`drive_input`]: DwApbGpioSim::drive_input
+#[derive(Clone, Debug)]
+pub struct DwApbGpioSim {
+    base: usize,
+    state: Arc<Mutex<SimState>>,
+}
+
+impl DwApbGpioSim {
+    pub fn new(base: usize) -> Self {
+        Self { base, state: Arc::new(Mutex::new(SimState::default())) }
+    }
+
+    pub fn for_port(port: GpioPort) -> Self {
+        Self::new(port.base_address())
+    }
+
+    /// Models a newer block with the `int_bothedge` register.
+    pub fn with_bothedge(self) -> Self {
+        self.state.lock().unwrap().has_bothedge = true;
+        self
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// Drives `line` externally. Only visible while the line is an input.
+    pub fn drive_input(&self, line: u32, level: bool) {
+        let bit = 1 << line;
+        self.state.lock().unwrap().update(|s| match level {
+            false => s.external &= !bit,
+            true => s.external |= bit,
+        });
+    }
+
+    /// Drives every line in `mask` to the matching bit of `levels`.
+    pub fn drive_inputs(&self, mask: u32, levels: u32) {
+        self.state.lock().unwrap().update(|s| s.external = (s.external & !mask) | (levels & mask));
+    }
+
+    /// Level on the pad of `line`, whether it is driven by the chip or from
+    /// outside.
+    pub fn pad_level(&self, line: u32) -> bool {
+        self.state.lock().unwrap().ext_porta() & (1 << line) != 0
+    }
+
+    /// Level driven by the chip, or `None` while `line` is an input.
+    pub fn output_level(&self, line: u32) -> Option<bool> {
+        let state = self.state.lock().unwrap();
+        let bit = 1 << line;
+        (state.swporta_ddr & bit != 0).then_some(state.swporta_dr & bit != 0)
+    }
+
+    /// Whether the block's interrupt output is asserted.
+    pub fn irq_asserted(&self) -> bool {
+        self.state.lock().unwrap().intstatus() != 0
+    }
+
+    fn offset(&self, addr: usize) -> Option<usize> {
+        addr.checked_sub(self.base).filter(|offset| *offset < size_of::<DuoGpio>())
+    }
+
+    fn registers(&self) -> Result<&'static DuoGpio> {
+        DuoGpio::new(self.base)
+    }
+}
+
+impl RegisterIo for DwApbGpioSim {
+    fn map(base: usize, _len: usize) -> Result<Self> {
+        Ok(Self::new(base))
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        if self.offset(addr).is_none() {
+            return Err(GpioError::OutOfRange { addr, base: self.base, len: size_of::<DuoGpio>() });
+        }
+
+        let regs = self.registers()?;
+        let state = self.state.lock().unwrap();
+        let val = match addr {
+            a if a == regs.swporta_dr() => state.swporta_dr,
+            a if a == regs.swporta_ddr() => state.swporta_ddr,
+            a if a == regs.inten() => state.inten,
+            a if a == regs.intmask() => state.intmask,
+            a if a == regs.inttype_level() => state.inttype_level,
+            a if a == regs.int_polarity() => state.int_polarity,
+            a if a == regs.intstatus() => state.intstatus(),
+            a if a == regs.raw_intstatus() => state.raw_intstatus(),
+            a if a == regs.debounce() => state.debounce,
+            a if a == regs.ext_porta() => state.ext_porta(),
+            a if a == regs.ls_sync() => state.ls_sync,
+            a if a == regs.int_bothedge() => state.int_bothedge,
+            // porta_eoi is write-only, the reserved words read as zero.
+            _ => 0,
+        };
+
+        Ok(val)
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        if self.offset(addr).is_none() {
+            return Err(GpioError::OutOfRange { addr, base: self.base, len: size_of::<DuoGpio>() });
+        }
+
+        let regs = self.registers()?;
+        let mut state = self.state.lock().unwrap();
+        match addr {
+            a if a == regs.swporta_dr() => state.update(|s| s.swporta_dr = val),
+            a if a == regs.swporta_ddr() => state.update(|s| s.swporta_ddr = val),
+            a if a == regs.inten() => {
+                state.inten = val;
+                // Disabling a line's interrupt drops whatever it had latched.
+                state.edge_status &= val;
+            },
+            a if a == regs.intmask() => state.intmask = val,
+            a if a == regs.inttype_level() => state.inttype_level = val,
+            a if a == regs.int_polarity() => state.int_polarity = val,
+            a if a == regs.debounce() => state.debounce = val,
+            a if a == regs.porta_eoi() => state.edge_status &= !val,
+            a if a == regs.ls_sync() => state.ls_sync = val,
+            a if a == regs.int_bothedge() && state.has_bothedge => state.int_bothedge = val,
+            // intstatus, raw_intstatus and ext_porta are read-only.
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    fn claim_scope(&self) -> Option<ClaimScope> {
+        Some(ClaimScope::Instance(Arc::as_ptr(&self.state) as usize))
+    }
+}
@@ -0,0 +1,523 @@
+//! SPI masters through the kernel's `/dev/spidevB.C` interface.
+//!
+//! [`Spi`] configures the device and issues transfers through an
+//! [`SpiDevOps`] backend: [`DuoSpiDev`] for spidev and [`FakeSpiDev`] for
+//! host tests. The chip select can be left to the controller or driven
+//! from any [`Gpio`] pin.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::duo::MilkVDuoGpio;
+use crate::{Gpio, GpioDirection, GpioError, Result};
+
+pub const SPI_CPHA: u8 = 0x01;
+pub const SPI_CPOL: u8 = 0x02;
+pub const SPI_CS_HIGH: u8 = 0x04;
+pub const SPI_NO_CS: u8 = 0x40;
+
+const SPI_IOC_MAGIC: u32 = b'k' as u32;
+
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | (SPI_IOC_MAGIC << 8) | nr
+}
+
+pub const SPI_IOC_WR_MODE: u32 = ioc(1, 1, 1);
+pub const SPI_IOC_WR_LSB_FIRST: u32 = ioc(1, 2, 1);
+pub const SPI_IOC_WR_BITS_PER_WORD: u32 = ioc(1, 3, 1);
+pub const SPI_IOC_WR_MAX_SPEED_HZ: u32 = ioc(1, 4, 4);
+
+/// `SPI_IOC_MESSAGE(n)`
+pub const fn spi_ioc_message(n: usize) -> u32 {
+    ioc(1, 0, n * size_of::<SpiIocTransfer>())
+}
+
+/// The ioctl size field is 14 bits wide, which bounds the transfers in one
+/// `SPI_IOC_MESSAGE`.
+pub const SPI_MAX_TRANSFERS: usize = ((1 << 14) - 1) / size_of::<SpiIocTransfer>();
+
+/// `struct spi_ioc_transfer`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpiIocTransfer {
+    pub tx_buf: u64,
+    pub rx_buf: u64,
+    pub len: u32,
+    pub speed_hz: u32,
+    pub delay_usecs: u16,
+    pub bits_per_word: u8,
+    pub cs_change: u8,
+    pub tx_nbits: u8,
+    pub rx_nbits: u8,
+    pub word_delay_usecs: u8,
+    pub pad: u8,
+}
+
+/// Clock polarity and phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpiMode {
+    /// Idle low, sample on the rising edge.
+    #[default]
+    Mode0,
+    /// Idle low, sample on the falling edge.
+    Mode1,
+    /// Idle high, sample on the falling edge.
+    Mode2,
+    /// Idle high, sample on the rising edge.
+    Mode3,
+}
+
+impl SpiMode {
+    pub fn bits(&self) -> u8 {
+        match self {
+            Self::Mode0 => 0,
+            Self::Mode1 => SPI_CPHA,
+            Self::Mode2 => SPI_CPOL,
+            Self::Mode3 => SPI_CPOL | SPI_CPHA,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpiConfig {
+    pub mode: SpiMode,
+    pub bits_per_word: u8,
+    pub max_speed_hz: u32,
+    pub lsb_first: bool,
+    /// Chip select is active high.
+    pub cs_high: bool,
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        Self {
+            mode: SpiMode::Mode0,
+            bits_per_word: 8,
+            max_speed_hz: 1_000_000,
+            lsb_first: false,
+            cs_high: false,
+        }
+    }
+}
+
+/// The buffers of one transfer. Reads clock out zeros, and both halves of
+/// a full-duplex [`Transfer`](SpiBuffers::Transfer) have the same length.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SpiBuffers<'a> {
+    Write(&'a [u8]),
+    Read(&'a mut [u8]),
+    Transfer {
+        read: &'a mut [u8],
+        write: &'a [u8],
+    },
+    TransferInPlace(&'a mut [u8]),
+    /// Clocks nothing, for a delay on its own.
+    Empty,
+}
+
+/// One transfer of an `SPI_IOC_MESSAGE`. The chip select stays asserted
+/// between transfers unless `cs_change` is set.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SpiTransfer<'a> {
+    pub buffers: SpiBuffers<'a>,
+    /// Overrides the device's speed for this transfer, 0 keeps it.
+    pub speed_hz: u32,
+    /// Delay after the transfer, before the chip select changes.
+    pub delay_us: u16,
+    pub cs_change: bool,
+}
+
+impl<'a> SpiTransfer<'a> {
+    fn new(buffers: SpiBuffers<'a>) -> Self {
+        Self { buffers, speed_hz: 0, delay_us: 0, cs_change: false }
+    }
+
+    pub fn write(write: &'a [u8]) -> Self {
+        Self::new(SpiBuffers::Write(write))
+    }
+
+    pub fn read(read: &'a mut [u8]) -> Self {
+        Self::new(SpiBuffers::Read(read))
+    }
+
+    pub fn transfer(read: &'a mut [u8], write: &'a [u8]) -> Self {
+        Self::new(SpiBuffers::Transfer { read, write })
+    }
+
+    pub fn transfer_in_place(buf: &'a mut [u8]) -> Self {
+        Self::new(SpiBuffers::TransferInPlace(buf))
+    }
+
+    pub fn delay(delay_us: u16) -> Self {
+        Self { delay_us, ..Self::new(SpiBuffers::Empty) }
+    }
+
+    pub fn with_delay(self, delay_us: u16) -> Self {
+        Self { delay_us, ..self }
+    }
+
+    pub fn with_speed(self, speed_hz: u32) -> Self {
+        Self { speed_hz, ..self }
+    }
+
+    pub fn with_cs_change(self, cs_change: bool) -> Self {
+        Self { cs_change, ..self }
+    }
+
+    /// Number of bytes clocked.
+    pub fn len(&self) -> usize {
+        match &self.buffers {
+            SpiBuffers::Write(buf) => buf.len(),
+            SpiBuffers::Read(buf) | SpiBuffers::TransferInPlace(buf) => buf.len(),
+            SpiBuffers::Transfer { read, write } => read.len().max(write.len()),
+            SpiBuffers::Empty => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Checks what spidev would otherwise read or write out of bounds for.
+fn validate(transfers: &[SpiTransfer<'_>]) -> Result<()> {
+    if transfers.len() > SPI_MAX_TRANSFERS {
+        return Err(GpioError::TooManySpiTransfers { transfers: transfers.len() });
+    }
+    for transfer in transfers {
+        if let SpiBuffers::Transfer { read, write } = &transfer.buffers {
+            if read.len() != write.len() {
+                return Err(GpioError::SpiLengthMismatch { read: read.len(), write: write.len() });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The ioctl layer of a spidev device.
+///
+/// [`DuoSpiDev`] talks to `/dev/spidevB.C`; tests can substitute
+/// [`FakeSpiDev`] or a mock.
+pub trait SpiDevOps {
+    /// Issues `SPI_IOC_WR_MODE` with `SPI_*` mode bits.
+    fn set_mode(&self, mode: u8) -> Result<()>;
+    fn set_lsb_first(&self, lsb_first: bool) -> Result<()>;
+    fn set_bits_per_word(&self, bits: u8) -> Result<()>;
+    fn set_max_speed_hz(&self, hz: u32) -> Result<()>;
+    /// Issues one `SPI_IOC_MESSAGE` with all of `transfers`.
+    fn transfer(&self, transfers: &mut [SpiTransfer<'_>]) -> Result<()>;
+}
+
+/// A `/dev/spidevB.C` device.
+pub struct DuoSpiDev {
+    path: PathBuf,
+    dev: File,
+}
+
+impl DuoSpiDev {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| GpioError::open(&path, e))?;
+
+        Ok(Self { path, dev })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn ioctl<T>(&self, ioctl: &'static str, request: u32, arg: *const T) -> Result<()> {
+        let result = unsafe { libc::ioctl(self.dev.as_raw_fd(), request as _, arg) };
+
+        if result == -1 {
+            Err(GpioError::Ioctl { ioctl, source: io::Error::last_os_error() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl SpiDevOps for DuoSpiDev {
+    fn set_mode(&self, mode: u8) -> Result<()> {
+        self.ioctl("SPI_IOC_WR_MODE", SPI_IOC_WR_MODE, &mode)
+    }
+
+    fn set_lsb_first(&self, lsb_first: bool) -> Result<()> {
+        self.ioctl("SPI_IOC_WR_LSB_FIRST", SPI_IOC_WR_LSB_FIRST, &u8::from(lsb_first))
+    }
+
+    fn set_bits_per_word(&self, bits: u8) -> Result<()> {
+        self.ioctl("SPI_IOC_WR_BITS_PER_WORD", SPI_IOC_WR_BITS_PER_WORD, &bits)
+    }
+
+    fn set_max_speed_hz(&self, hz: u32) -> Result<()> {
+        self.ioctl("SPI_IOC_WR_MAX_SPEED_HZ", SPI_IOC_WR_MAX_SPEED_HZ, &hz)
+    }
+
+    fn transfer(&self, transfers: &mut [SpiTransfer<'_>]) -> Result<()> {
+        validate(transfers)?;
+        let raw: Vec<SpiIocTransfer> = transfers
+            .iter_mut()
+            .map(|transfer| {
+                let (tx, rx) = match &mut transfer.buffers {
+                    SpiBuffers::Write(buf) => (buf.as_ptr(), std::ptr::null_mut()),
+                    SpiBuffers::Read(buf) => (std::ptr::null(), buf.as_mut_ptr()),
+                    SpiBuffers::Transfer { read, write } => (write.as_ptr(), read.as_mut_ptr()),
+                    // spidev copies the tx buffer in before copying rx out.
+                    SpiBuffers::TransferInPlace(buf) => (buf.as_ptr(), buf.as_mut_ptr()),
+                    SpiBuffers::Empty => (std::ptr::null(), std::ptr::null_mut()),
+                };
+                SpiIocTransfer {
+                    tx_buf: tx as u64,
+                    rx_buf: rx as u64,
+                    len: transfer.len() as u32,
+                    speed_hz: transfer.speed_hz,
+                    delay_usecs: transfer.delay_us,
+                    cs_change: transfer.cs_change.into(),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        self.ioctl("SPI_IOC_MESSAGE", spi_ioc_message(raw.len()), raw.as_ptr())
+    }
+}
+
+/// An SPI device on a bus, with its chip select.
+///
+/// With a GPIO chip select the controller's own is disabled with
+/// `SPI_NO_CS`, and the pin is held asserted for a whole
+/// [`transaction`](Spi::transaction), so `cs_change` has no effect.
+pub struct Spi<D: SpiDevOps = DuoSpiDev, G: Gpio = MilkVDuoGpio<'static>> {
+    ops: D,
+    config: SpiConfig,
+    cs: Option<G>,
+}
+
+impl Spi<DuoSpiDev> {
+    /// Opens `/dev/spidev{bus}.{chip_select}`. On the Duo, SPI2 sits on
+    /// the SD1 pads, see [`Pinmux::set_function_named`] for muxing them.
+    ///
+    /// [`Pinmux::set_function_named`]: crate::pinmux::Pinmux::set_function_named
+    pub fn open(bus: u32, chip_select: u32, config: &SpiConfig) -> Result<Self> {
+        Self::new(DuoSpiDev::open(format!("/dev/spidev{bus}.{chip_select}"))?, config)
+    }
+}
+
+impl<D: SpiDevOps> Spi<D> {
+    pub fn new(ops: D, config: &SpiConfig) -> Result<Self> {
+        let spi = Self { ops, config: *config, cs: None };
+        spi.apply_config()?;
+
+        Ok(spi)
+    }
+}
+
+impl<D: SpiDevOps, G: Gpio> Spi<D, G> {
+    /// Uses `cs` as the chip select. The pin is driven inactive before it
+    /// is switched to an output.
+    pub fn with_chip_select(ops: D, config: &SpiConfig, cs: G) -> Result<Self> {
+        cs.write_pin(!config.cs_high)?;
+        cs.init(GpioDirection::GpioOutput)?;
+        let spi = Self { ops, config: *config, cs: Some(cs) };
+        spi.apply_config()?;
+
+        Ok(spi)
+    }
+
+    pub fn ops(&self) -> &D {
+        &self.ops
+    }
+
+    pub fn chip_select(&self) -> Option<&G> {
+        self.cs.as_ref()
+    }
+
+    pub fn config(&self) -> &SpiConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: &SpiConfig) -> Result<()> {
+        if let Some(cs) = &self.cs {
+            cs.write_pin(!config.cs_high)?;
+        }
+        self.config = *config;
+        self.apply_config()
+    }
+
+    fn apply_config(&self) -> Result<()> {
+        let mut mode = self.config.mode.bits();
+        if self.cs.is_some() {
+            mode |= SPI_NO_CS;
+        } else if self.config.cs_high {
+            mode |= SPI_CS_HIGH;
+        }
+        self.ops.set_mode(mode)?;
+        self.ops.set_lsb_first(self.config.lsb_first)?;
+        self.ops.set_bits_per_word(self.config.bits_per_word)?;
+        self.ops.set_max_speed_hz(self.config.max_speed_hz)
+    }
+
+    /// Runs `transfers` back to back with the chip select asserted.
+    pub fn transaction(&self, transfers: &mut [SpiTransfer<'_>]) -> Result<()> {
+        validate(transfers)?;
+
+        let Some(cs) = &self.cs else {
+            return self.ops.transfer(transfers);
+        };
+        cs.write_pin(self.config.cs_high)?;
+        let result = self.ops.transfer(transfers);
+        // Release the chip select even if the transfer failed.
+        let released = cs.write_pin(!self.config.cs_high);
+
+        result.and(released)
+    }
+
+    pub fn write(&self, write: &[u8]) -> Result<()> {
+        self.transaction(&mut [SpiTransfer::write(write)])
+    }
+
+    pub fn read(&self, read: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiTransfer::read(read)])
+    }
+
+    /// Full-duplex transfer, `read` and `write` must be the same length.
+    pub fn transfer(&self, read: &mut [u8], write: &[u8]) -> Result<()> {
+        self.transaction(&mut [SpiTransfer::transfer(read, write)])
+    }
+
+    pub fn transfer_in_place(&self, buf: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiTransfer::transfer_in_place(buf)])
+    }
+}
+
+#[derive(Debug, Default)]
+struct FakeSpiState {
+    mode: u8,
+    lsb_first: bool,
+    bits_per_word: u8,
+    max_speed_hz: u32,
+    loopback: bool,
+    responses: VecDeque<u8>,
+    written: Vec<u8>,
+    messages: usize,
+}
+
+/// An in-memory spidev device.
+///
+/// Every byte clocked out is recorded. The bytes clocked in come from
+/// [`queue_response`](FakeSpiDev::queue_response), then idle high as
+/// 0xff, or, with [`loopback`](FakeSpiDev::loopback), echo what was clocked
+/// out as if MOSI were wired to MISO. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct FakeSpiDev {
+    state: Arc<Mutex<FakeSpiState>>,
+}
+
+impl FakeSpiDev {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn loopback() -> Self {
+        let fake = Self::default();
+        fake.state.lock().unwrap().loopback = true;
+        fake
+    }
+
+    pub fn queue_response(&self, bytes: &[u8]) {
+        self.state.lock().unwrap().responses.extend(bytes);
+    }
+
+    /// Everything clocked out so far.
+    pub fn written(&self) -> Vec<u8> {
+        self.state.lock().unwrap().written.clone()
+    }
+
+    /// Number of `SPI_IOC_MESSAGE` calls so far.
+    pub fn messages(&self) -> usize {
+        self.state.lock().unwrap().messages
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.state.lock().unwrap().mode
+    }
+
+    pub fn lsb_first(&self) -> bool {
+        self.state.lock().unwrap().lsb_first
+    }
+
+    pub fn bits_per_word(&self) -> u8 {
+        self.state.lock().unwrap().bits_per_word
+    }
+
+    pub fn max_speed_hz(&self) -> u32 {
+        self.state.lock().unwrap().max_speed_hz
+    }
+}
+
+impl SpiDevOps for FakeSpiDev {
+    fn set_mode(&self, mode: u8) -> Result<()> {
+        self.state.lock().unwrap().mode = mode;
+        Ok(())
+    }
+
+    fn set_lsb_first(&self, lsb_first: bool) -> Result<()> {
+        self.state.lock().unwrap().lsb_first = lsb_first;
+        Ok(())
+    }
+
+    fn set_bits_per_word(&self, bits: u8) -> Result<()> {
+        self.state.lock().unwrap().bits_per_word = bits;
+        Ok(())
+    }
+
+    fn set_max_speed_hz(&self, hz: u32) -> Result<()> {
+        self.state.lock().unwrap().max_speed_hz = hz;
+        Ok(())
+    }
+
+    fn transfer(&self, transfers: &mut [SpiTransfer<'_>]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.messages += 1;
+
+        for transfer in transfers {
+            let len = transfer.len();
+            let mut tx: Vec<u8> = match &transfer.buffers {
+                SpiBuffers::Write(buf) => buf.to_vec(),
+                SpiBuffers::Transfer { write, .. } => write.to_vec(),
+                SpiBuffers::TransferInPlace(buf) => buf.to_vec(),
+                SpiBuffers::Read(_) | SpiBuffers::Empty => Vec::new(),
+            };
+            tx.resize(len, 0);
+            let rx: Vec<u8> = if state.loopback {
+                tx.clone()
+            } else {
+                (0..len).map(|_| state.responses.pop_front().unwrap_or(0xff)).collect()
+            };
+            state.written.extend(&tx);
+
+            match &mut transfer.buffers {
+                SpiBuffers::Read(buf) | SpiBuffers::TransferInPlace(buf) => {
+                    buf.copy_from_slice(&rx)
+                },
+                SpiBuffers::Transfer { read, .. } => {
+                    let n = read.len();
+                    read.copy_from_slice(&rx[..n]);
+                },
+                SpiBuffers::Write(_) | SpiBuffers::Empty => {},
+            }
+        }
+
+        Ok(())
+    }
+}
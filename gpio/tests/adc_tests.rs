@@ -0,0 +1,148 @@
+// tests/adc_tests.rs
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gpio::{FileSystemOps, GpioError, Result};
+
+/// Builds an IIO device directory under the temp dir, with `attrs` as
+/// `(name, contents)` pairs.
+fn fake_iio_device(name: &str, attrs: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("duo-adc-tests-{}", std::process::id()))
+        .join(name)
+        .join("iio:device0");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (attr, contents) in attrs {
+        fs::write(dir.join(attr), contents).unwrap();
+    }
+    dir
+}
+
+/// An IIO tree held in memory, as `(path, contents)` pairs.
+struct FakeIioFs(HashMap<PathBuf, String>);
+
+impl FakeIioFs {
+    fn new(attrs: &[(&str, &str)]) -> Self {
+        Self(attrs.iter().map(|(path, value)| (PathBuf::from(path), value.to_string())).collect())
+    }
+}
+
+impl FileSystemOps for FakeIioFs {
+    fn write(&self, path: &Path, _content: &[u8]) -> Result<()> {
+        Err(GpioError::Sysfs { path: path.to_path_buf(), source: std::io::ErrorKind::Other.into() })
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        self.0.get(path).cloned().ok_or_else(|| GpioError::Sysfs {
+            path: path.to_path_buf(),
+            source: std::io::ErrorKind::NotFound.into(),
+        })
+    }
+
+    fn poll_priority(&self, _path: &Path, _timeout: Option<Duration>) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use gpio::adc::{self, Adc, AdcChannel, AdcIio, AdcRegisters, SARADC_BASE};
+    use gpio::duo::DuoFileSystem;
+    use gpio::gpio_mmap::MemRegisters;
+
+    use super::*;
+
+    fn adc1() -> AdcChannel {
+        AdcChannel::new(1).unwrap()
+    }
+
+    #[test]
+    fn test_channels() {
+        assert_eq!(adc1().index(), 0);
+        assert_eq!(AdcChannel::new(3).unwrap().index(), 2);
+        assert!(matches!(AdcChannel::new(0), Err(GpioError::InvalidAdcChannel { channel: 0 })));
+        assert!(AdcChannel::new(4).is_err());
+    }
+
+    #[test]
+    fn test_iio_shared_scale() {
+        let dir = fake_iio_device("shared", &[
+            ("in_voltage0_raw", "2048\n"),
+            ("in_voltage_scale", "0.805664062\n"),
+        ]);
+        let adc = AdcIio::with_path(&dir, DuoFileSystem);
+
+        assert_eq!(adc.read_raw(adc1()).unwrap(), 2048);
+        assert!((adc.read_mv(adc1()).unwrap() - 1650.0).abs() < 0.01);
+        assert_eq!(adc.read_average(adc1(), 4).unwrap(), 2048.0);
+
+        let err = adc.read_raw(AdcChannel::new(2).unwrap()).err().unwrap();
+        assert!(matches!(err, GpioError::Sysfs { .. }));
+    }
+
+    #[test]
+    fn test_iio_channel_scale() {
+        let dir = fake_iio_device("channel", &[
+            ("in_voltage1_raw", "1000\n"),
+            ("in_voltage1_scale", "0.5\n"),
+            ("in_voltage_scale", "1.0\n"),
+            ("in_voltage2_raw", "garbage\n"),
+        ]);
+        let adc = AdcIio::with_path(&dir, DuoFileSystem);
+
+        assert_eq!(adc.read_mv(AdcChannel::new(2).unwrap()).unwrap(), 500.0);
+        let err = adc.read_raw(AdcChannel::new(3).unwrap()).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidValue { .. }));
+    }
+
+    #[test]
+    fn test_register_conversion() {
+        let regs = MemRegisters::new();
+        regs.set(SARADC_BASE + 0x18, 0x8000 | 0x400);
+        let adc = AdcRegisters::with_registers(regs.clone()).with_reference_mv(1800.0);
+        let adc2 = AdcChannel::new(2).unwrap();
+
+        assert_eq!(adc.read_raw(adc2).unwrap(), 0x400);
+        assert_eq!(adc.read_mv(adc2).unwrap(), 450.0);
+        assert_eq!(regs.get(SARADC_BASE + 0x04), 0);
+
+        let err = adc.read_raw(adc1()).err().unwrap();
+        assert!(matches!(err, GpioError::AdcTimeout { channel: 1 }));
+    }
+
+    #[test]
+    fn test_open_probes_iio() {
+        let fs = FakeIioFs::new(&[
+            ("/sys/bus/iio/devices/iio:device0/in_voltage0_raw", "100\n"),
+            ("/sys/bus/iio/devices/iio:device0/in_voltage_scale", "0.5\n"),
+        ]);
+        let adc = adc::open(fs).unwrap();
+        assert_eq!(adc.read_mv(adc1()).unwrap(), 50.0);
+
+        // Without the driver the controller is driven directly.
+        let iio = AdcIio::with_path("/sys/bus/iio/devices/iio:device0", FakeIioFs::new(&[]));
+        let adc = adc::open_with::<MemRegisters, _>(iio).unwrap();
+        assert_eq!(adc.scale_mv(adc1()).unwrap(), 3300.0 / 4096.0);
+    }
+
+    #[test]
+    fn test_sample_at_rate() {
+        let regs = MemRegisters::new();
+        regs.set(SARADC_BASE + 0x14, 0x8000 | 0x123);
+        let adc = AdcRegisters::with_registers(regs);
+
+        let mut buf = [0; 5];
+        let start = Instant::now();
+        adc.sample(adc1(), 200.0, &mut buf).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(buf, [0x123; 5]);
+
+        let err = adc.sample(adc1(), 0.0, &mut buf).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidSampleRate { .. }));
+    }
+}
@@ -0,0 +1,102 @@
+// tests/board_tests.rs
+#[cfg(test)]
+mod tests {
+    use gpio::{Device, GpioError, GpioPort, Pin};
+
+    /// The Duo header from the official pinout, GP23 and GP24 being ADC and
+    /// GP25 the LED.
+    const DUO_PINOUT: [(&str, GpioPort, u32); 25] = [
+        ("GP0", GpioPort::Port0, 28),
+        ("GP1", GpioPort::Port0, 29),
+        ("GP2", GpioPort::Pwr, 26),
+        ("GP3", GpioPort::Pwr, 25),
+        ("GP4", GpioPort::Pwr, 19),
+        ("GP5", GpioPort::Pwr, 20),
+        ("GP6", GpioPort::Pwr, 23),
+        ("GP7", GpioPort::Pwr, 22),
+        ("GP8", GpioPort::Pwr, 21),
+        ("GP9", GpioPort::Pwr, 18),
+        ("GP10", GpioPort::Port2, 9),
+        ("GP11", GpioPort::Port2, 10),
+        ("GP12", GpioPort::Port0, 16),
+        ("GP13", GpioPort::Port0, 17),
+        ("GP14", GpioPort::Port0, 14),
+        ("GP15", GpioPort::Port0, 15),
+        ("GP16", GpioPort::Port0, 23),
+        ("GP17", GpioPort::Port0, 24),
+        ("GP18", GpioPort::Port0, 22),
+        ("GP19", GpioPort::Port0, 25),
+        ("GP20", GpioPort::Port0, 27),
+        ("GP21", GpioPort::Port0, 26),
+        ("GP22", GpioPort::Pwr, 4),
+        ("GP26", GpioPort::Port1, 3),
+        ("GP27", GpioPort::Port1, 6),
+    ];
+
+    #[test]
+    fn test_header_names() {
+        for device in [Device::Duo, Device::Duo256M] {
+            for (name, port, line) in DUO_PINOUT {
+                let pin = device.pin(name).unwrap();
+                assert_eq!((pin.port(), pin.line()), (port, line), "{name}");
+            }
+            assert_eq!(device.header().len(), DUO_PINOUT.len());
+        }
+
+        assert_eq!(Device::Duo.pin("GP0").unwrap().sysfs_number(), 508);
+        let pin = Device::Duo.pin("gp22").unwrap();
+        assert_eq!((pin.port(), pin.line()), (GpioPort::Pwr, 4));
+    }
+
+    #[test]
+    fn test_duos_header_names() {
+        for (name, port, line) in Device::DuoS.header() {
+            let pin = Device::DuoS.pin(name).unwrap();
+            assert_eq!((pin.port(), pin.line()), (*port, *line), "{name}");
+        }
+
+        // XGPIOB[20] is not bonded on the CV1800B, only on the SG2000.
+        let pin = Device::DuoS.pin("b20").unwrap();
+        assert_eq!((pin.port(), pin.line()), (GpioPort::Port1, 20));
+        assert_eq!(Device::DuoS.pin("XGPIOB[20]").unwrap(), pin);
+        assert!(Pin::new(GpioPort::Port1, 20).is_err());
+        assert!(Device::Duo.pin("XGPIOB[20]").is_err());
+        assert!(Device::Duo.pin("B20").is_err());
+    }
+
+    #[test]
+    fn test_led_per_board() {
+        assert_eq!(Device::Duo.pin("LED").unwrap().sysfs_number(), 440);
+        assert_eq!(Device::Duo.pin("GP25").unwrap(), Device::Duo.led());
+        assert_eq!(Device::Duo256M.pin("LED").unwrap().sysfs_number(), 354);
+        assert_eq!(Device::Duo256M.pin("GP25").unwrap(), Device::Duo256M.led());
+        assert_eq!(Device::DuoS.pin("LED").unwrap().sysfs_number(), 509);
+    }
+
+    #[test]
+    fn test_soc_names() {
+        assert_eq!(Device::Duo.pin("XGPIOC[24]").unwrap(), Pin::new(GpioPort::Port2, 24).unwrap());
+        assert_eq!(
+            Device::Duo256M.pin("PWR_GPIO[4]").unwrap(),
+            Pin::new(GpioPort::Pwr, 4).unwrap()
+        );
+        assert_eq!(Device::DuoS.pin("PWR_GPIO[4]").unwrap(), Pin::new(GpioPort::Pwr, 4).unwrap());
+        assert!(matches!(Device::Duo.pin("XGPIOC[31]").err().unwrap(), GpioError::InvalidPin {
+            port: GpioPort::Port2,
+            pin: 31
+        }));
+    }
+
+    #[test]
+    fn test_unknown_names() {
+        for name in ["GP23", "GP24", "GP28", "GP99", "XGPIOE[1]", "XGPIOA[x]", ""] {
+            assert!(matches!(Device::Duo.pin(name).err().unwrap(), GpioError::UnknownPinName {
+                device: Device::Duo,
+                ..
+            }));
+        }
+        // The Duo S header uses SoC names, so it has no GP names or GP25.
+        assert!(Device::DuoS.pin("GP0").is_err());
+        assert!(Device::DuoS.pin("GP25").is_err());
+    }
+}
@@ -0,0 +1,85 @@
+// tests/claim_tests.rs
+use std::path::PathBuf;
+
+fn run_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("duo-gpio-claim-tests-{}", std::process::id()));
+    std::env::set_var("DUO_GPIO_RUN_DIR", &dir);
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+
+    use gpio::claim::{claim_path, ClaimScope, PinClaim, PinOwner};
+    use gpio::{GpioError, GpioPort, Pin};
+
+    use super::*;
+
+    #[test]
+    fn test_claim_in_process() {
+        let pin = Pin::new(GpioPort::Pwr, 0).unwrap();
+        let scope = ClaimScope::Instance(1);
+        let claim = PinClaim::take(pin, scope).unwrap();
+        assert!(matches!(PinClaim::owner(pin, scope), Some(PinOwner::Thread { .. })));
+
+        let err = PinClaim::take(pin, scope).unwrap_err();
+        assert!(
+            matches!(err, GpioError::PinClaimed { pin: p, owner: PinOwner::Thread { .. } } if p == pin)
+        );
+        assert!(err.to_string().contains("of this process"));
+
+        drop(claim);
+        assert!(PinClaim::owner(pin, scope).is_none());
+        PinClaim::take(pin, scope).unwrap();
+    }
+
+    #[test]
+    fn test_claims_per_instance() {
+        let pin = Pin::new(GpioPort::Pwr, 2).unwrap();
+        let _first = PinClaim::take(pin, ClaimScope::Instance(1)).unwrap();
+        let _second = PinClaim::take(pin, ClaimScope::Instance(2)).unwrap();
+        assert!(PinClaim::take(pin, ClaimScope::Instance(2)).is_err());
+        assert!(PinClaim::take_for(pin, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_claim_across_processes() {
+        let dir = run_dir();
+        let pin = Pin::new(GpioPort::Pwr, 1).unwrap();
+        let path = claim_path(pin);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        // Stand in for another process: a separate open file description
+        // holding the lock, stamped with a PID.
+        let mut other =
+            OpenOptions::new().create(true).truncate(true).write(true).open(&path).unwrap();
+        writeln!(other, "4242").unwrap();
+        assert_eq!(unsafe { libc::flock(other.as_raw_fd(), libc::LOCK_EX) }, 0);
+
+        let err = PinClaim::take(pin, ClaimScope::System).unwrap_err();
+        assert!(matches!(err, GpioError::PinClaimed {
+            owner: PinOwner::Process { pid: Some(4242), .. },
+            ..
+        }));
+        assert!(PinClaim::owner(pin, ClaimScope::System).is_none());
+
+        // Held but not stamped yet: the owner is unknown, not PID 0.
+        other.set_len(0).unwrap();
+        let err = PinClaim::take(pin, ClaimScope::System).unwrap_err();
+        assert!(matches!(err, GpioError::PinClaimed {
+            owner: PinOwner::Process { pid: None, name: None },
+            ..
+        }));
+        assert!(err.to_string().contains("PID unknown"));
+
+        drop(other);
+        let claim = PinClaim::take(pin, ClaimScope::System).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+        drop(claim);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,360 @@
+// tests/duo_gpio_tests.rs
+use std::sync::{Arc, Mutex};
+
+use gpio::claim::ClaimScope;
+use gpio::duo::{DuoGpio, GpioPortHandle, MilkVDuoGpio, GPIO2_BASE};
+use gpio::gpio_mmap::MemRegisters;
+use gpio::GpioPort::Port2;
+use gpio::{RegisterIo, Result};
+
+/// Records every register write so tests can check how many bus writes an
+/// operation takes.
+#[derive(Clone, Default)]
+struct RecordingRegisters {
+    regs: MemRegisters,
+    writes: Arc<Mutex<Vec<(usize, u32)>>>,
+}
+
+impl RecordingRegisters {
+    fn writes(&self) -> Vec<(usize, u32)> {
+        self.writes.lock().unwrap().clone()
+    }
+}
+
+impl RegisterIo for RecordingRegisters {
+    fn map(_base: usize, _len: usize) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        self.regs.read32(addr)
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        self.writes.lock().unwrap().push((addr, val));
+        self.regs.write32(addr, val)
+    }
+
+    fn claim_scope(&self) -> Option<ClaimScope> {
+        self.regs.claim_scope()
+    }
+}
+
+fn duo_gpio(pin: u32, regs: &MemRegisters) -> MilkVDuoGpio<'static, MemRegisters> {
+    MilkVDuoGpio::with_registers(Port2, pin, regs.clone()).unwrap()
+}
+
+fn registers() -> &'static DuoGpio {
+    DuoGpio::new(GPIO2_BASE).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use gpio::claim::PinOwner;
+    use gpio::duo::DIV_CLK_GPIO_DB;
+    use gpio::GpioDirection::{GpioInput, GpioOutput};
+    use gpio::{DropPolicy, Gpio, GpioError, GpioPort, IntLevelType, IntPolarity, Pin};
+
+    use super::*;
+
+    #[test]
+    fn test_init_sets_direction_bit_only() {
+        let regs = MemRegisters::new();
+        regs.set(registers().swporta_ddr(), 0x0000_00f0);
+
+        let gpio = duo_gpio(24, &regs);
+        gpio.init(GpioOutput).unwrap();
+        assert_eq!(regs.get(registers().swporta_ddr()), 0x0100_00f0);
+
+        gpio.init(GpioInput).unwrap();
+        assert_eq!(regs.get(registers().swporta_ddr()), 0x0000_00f0);
+    }
+
+    #[test]
+    fn test_write_pin_preserves_other_lines() {
+        let regs = MemRegisters::new();
+        regs.set(registers().swporta_dr(), 0x8000_0001);
+
+        let gpio = duo_gpio(24, &regs);
+        gpio.write_pin(true).unwrap();
+        assert_eq!(regs.get(registers().swporta_dr()), 0x8100_0001);
+
+        gpio.write_pin(false).unwrap();
+        assert_eq!(regs.get(registers().swporta_dr()), 0x8000_0001);
+    }
+
+    #[test]
+    fn test_read_pin_uses_ext_porta() {
+        let regs = MemRegisters::new();
+        let gpio = duo_gpio(3, &regs);
+
+        regs.set(registers().swporta_dr(), 1 << 3);
+        regs.set(registers().ext_porta(), !(1 << 3));
+        assert_eq!(gpio.read_pin().unwrap(), 0);
+        assert_eq!(gpio.read_output_latch().unwrap(), 1);
+
+        regs.set(registers().swporta_dr(), !(1 << 3));
+        regs.set(registers().ext_porta(), 1 << 3);
+        assert_eq!(gpio.read_pin().unwrap(), 1);
+        assert_eq!(gpio.read_output_latch().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_direction() {
+        let regs = MemRegisters::new();
+        let gpio = duo_gpio(3, &regs);
+        assert_eq!(gpio.direction().unwrap(), GpioInput);
+
+        gpio.init(GpioOutput).unwrap();
+        assert_eq!(gpio.direction().unwrap(), GpioOutput);
+    }
+
+    #[test]
+    fn test_interrupt_enable_and_mask() {
+        let regs = MemRegisters::new();
+        let gpio = duo_gpio(5, &regs);
+
+        gpio.enable_interrupt().unwrap();
+        gpio.enable_interrupt_mask().unwrap();
+        assert_eq!(regs.get(registers().inten()), 1 << 5);
+        assert_eq!(regs.get(registers().intmask()), 1 << 5);
+
+        gpio.disable_interrupt().unwrap();
+        gpio.disable_interrupt_mask().unwrap();
+        assert_eq!(regs.get(registers().inten()), 0);
+        assert_eq!(regs.get(registers().intmask()), 0);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let regs = MemRegisters::new();
+        regs.set(registers().debounce(), 0x1);
+        let gpio = duo_gpio(7, &regs);
+
+        gpio.enable_debounce().unwrap();
+        assert_eq!(regs.get(registers().debounce()), 0x81);
+
+        gpio.disable_debounce().unwrap();
+        assert_eq!(regs.get(registers().debounce()), 0x1);
+    }
+
+    #[test]
+    fn test_interrupt_level_type_and_polarity() {
+        let regs = MemRegisters::new();
+        regs.set(registers().inttype_level(), u32::MAX);
+        regs.set(registers().int_polarity(), u32::MAX);
+        let gpio = duo_gpio(2, &regs);
+
+        gpio.set_interrupt_level_type(IntLevelType::LevelSensitive).unwrap();
+        gpio.set_interrupt_polarity(IntPolarity::ActiveLow).unwrap();
+        assert_eq!(regs.get(registers().inttype_level()), !(1 << 2));
+        assert_eq!(regs.get(registers().int_polarity()), !(1 << 2));
+
+        gpio.set_interrupt_level_type(IntLevelType::EdgeSensitive).unwrap();
+        gpio.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+        assert_eq!(regs.get(registers().inttype_level()), u32::MAX);
+        assert_eq!(regs.get(registers().int_polarity()), u32::MAX);
+    }
+
+    #[test]
+    fn test_drop_resets_pin_to_input() {
+        let regs = MemRegisters::new();
+        {
+            let gpio = duo_gpio(24, &regs);
+            gpio.init(GpioOutput).unwrap();
+            assert_eq!(regs.read32(registers().swporta_ddr()).unwrap(), 1 << 24);
+        }
+        assert_eq!(regs.get(registers().swporta_ddr()), 0);
+    }
+
+    #[test]
+    fn test_line_is_claimed_in_process() {
+        let regs = MemRegisters::new();
+        let gpio = duo_gpio(24, &regs);
+
+        let err = MilkVDuoGpio::with_registers(Port2, 24, regs.clone()).err().unwrap();
+        assert!(matches!(err, GpioError::PinClaimed { owner: PinOwner::Thread { .. }, .. }));
+
+        // Another fake is another chip, so its line 24 is free.
+        duo_gpio(24, &MemRegisters::new());
+
+        drop(gpio);
+        duo_gpio(24, &regs);
+    }
+
+    #[test]
+    fn test_invalid_lines_are_rejected() {
+        let regs = MemRegisters::new();
+        for (port, line) in [(Port2, 32), (Port2, 31), (GpioPort::Pwr, 5), (GpioPort::Port3, 0)] {
+            let err = MilkVDuoGpio::with_registers(port, line, regs.clone()).err().unwrap();
+            assert!(
+                matches!(err, GpioError::InvalidPin { port: p, pin } if p == port && pin == line)
+            );
+        }
+        assert!(Pin::new(GpioPort::Pwr, 4).is_ok());
+    }
+
+    #[test]
+    fn test_port_write_masked_is_one_write() {
+        let regs = RecordingRegisters::default();
+        regs.regs.set(registers().swporta_dr(), 0x8000_0f0f);
+        let port = GpioPortHandle::with_registers(Port2, regs.clone()).unwrap();
+
+        port.write_masked(0x0000_00fc, 0x0000_00a4).unwrap();
+        assert_eq!(regs.writes(), vec![(registers().swporta_dr(), 0x8000_0fa7)]);
+
+        port.toggle_masked(0x0000_0f00).unwrap();
+        assert_eq!(regs.regs.get(registers().swporta_dr()), 0x8000_00a7);
+        assert_eq!(regs.writes().len(), 2);
+    }
+
+    #[test]
+    fn test_port_direction_and_read_all() {
+        let regs = MemRegisters::new();
+        regs.set(registers().swporta_ddr(), 0x0100_0000);
+        regs.set(registers().ext_porta(), u32::MAX);
+        let port = GpioPortHandle::with_registers(Port2, regs.clone()).unwrap();
+
+        port.set_direction_masked(0x0000_00fc, 0x0000_00f0).unwrap();
+        assert_eq!(port.direction().unwrap(), 0x0100_00f0);
+        assert_eq!(port.read_all().unwrap(), GpioPort::Port2.valid_lines());
+    }
+
+    #[test]
+    fn test_port_rejects_unbonded_lines() {
+        let regs = RecordingRegisters::default();
+        let port = GpioPortHandle::with_registers(Port2, regs.clone()).unwrap();
+
+        let err = port.write_masked(0x8000_0001 | 0x4, 0).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidPin { port: Port2, pin: 0 }));
+        assert!(regs.writes().is_empty());
+    }
+
+    #[test]
+    fn test_debounce_clock() {
+        let regs = MemRegisters::new();
+        regs.set(DIV_CLK_GPIO_DB, (25_000 << 16) | 0x9);
+        let gpio = duo_gpio(7, &regs).with_clock_registers(regs.clone());
+
+        let clock = gpio.debounce_clock().unwrap();
+        assert_eq!(clock.rate_hz(), 1000);
+        assert_eq!(clock.min_filter_time(), Duration::from_millis(1));
+        assert_eq!(clock.max_filter_time(), Duration::from_millis(2));
+
+        let clock = gpio.set_debounce_clock(10_000).unwrap();
+        assert_eq!(clock.divider, 2500);
+        assert_eq!(regs.get(DIV_CLK_GPIO_DB), (2500 << 16) | 0x9);
+        assert!(matches!(
+            gpio.set_debounce_clock(0),
+            Err(GpioError::InvalidDebounceClock { hz: 0 })
+        ));
+
+        // Without the select bit the reset default divider applies.
+        regs.set(DIV_CLK_GPIO_DB, (2500 << 16) | 0x1);
+        assert_eq!(gpio.debounce_clock().unwrap().rate_hz(), 100_000);
+        gpio.set_debounce_clock(1000).unwrap();
+        assert_eq!(regs.get(DIV_CLK_GPIO_DB), (25_000 << 16) | 0x9);
+    }
+
+    #[test]
+    fn test_level_sync() {
+        let regs = MemRegisters::new();
+        let gpio = duo_gpio(7, &regs);
+        assert!(!gpio.level_sync().unwrap());
+
+        gpio.set_level_sync(true).unwrap();
+        assert_eq!(regs.get(registers().ls_sync()), 1);
+        assert!(gpio.level_sync().unwrap());
+    }
+
+    #[test]
+    fn test_drop_policy_leave_as_is() {
+        let regs = MemRegisters::new();
+        {
+            let gpio = duo_gpio(24, &regs).with_drop_policy(DropPolicy::LeaveAsIs);
+            gpio.init(GpioOutput).unwrap();
+            gpio.write_pin(true).unwrap();
+        }
+        assert_eq!(regs.get(registers().swporta_ddr()), 1 << 24);
+        assert_eq!(regs.get(registers().swporta_dr()), 1 << 24);
+    }
+
+    #[test]
+    fn test_drop_policy_restore_is_bit_precise() {
+        let regs = RecordingRegisters::default();
+        let duo = registers();
+        regs.regs.set(duo.swporta_dr(), 1 << 5);
+        regs.regs.set(duo.inttype_level(), 1 << 5);
+        regs.regs.set(duo.debounce(), 1 << 5);
+        {
+            let gpio = MilkVDuoGpio::with_registers(Port2, 5, regs.clone())
+                .unwrap()
+                .with_drop_policy(DropPolicy::Restore);
+            assert!(gpio.snapshot().dr && gpio.snapshot().debounce && !gpio.snapshot().ddr);
+
+            gpio.init(GpioOutput).unwrap();
+            gpio.write_pin(false).unwrap();
+            gpio.enable_interrupt().unwrap();
+            gpio.disable_debounce().unwrap();
+            gpio.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+
+            // Another handle changes its own lines in the meantime.
+            regs.regs.set(duo.swporta_dr(), regs.regs.get(duo.swporta_dr()) | 0xf00);
+            regs.regs.set(duo.inten(), regs.regs.get(duo.inten()) | 1 << 6);
+        }
+        assert_eq!(regs.regs.get(duo.swporta_dr()), 0xf00 | 1 << 5);
+        assert_eq!(regs.regs.get(duo.swporta_ddr()), 0);
+        assert_eq!(regs.regs.get(duo.inten()), 1 << 6);
+        assert_eq!(regs.regs.get(duo.int_polarity()), 0);
+        assert_eq!(regs.regs.get(duo.inttype_level()), 1 << 5);
+        assert_eq!(regs.regs.get(duo.debounce()), 1 << 5);
+        assert_eq!(regs.writes().last().unwrap().0, duo.swporta_ddr());
+    }
+
+    #[test]
+    fn test_handles_are_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<MilkVDuoGpio<'static>>();
+        assert_send_sync::<GpioPortHandle<'static>>();
+    }
+
+    #[test]
+    fn test_concurrent_handles_keep_each_others_bits() {
+        let regs = MemRegisters::new();
+        std::thread::scope(|scope| {
+            for line in [2, 3, 4, 5] {
+                let gpio = duo_gpio(line, &regs);
+                scope.spawn(move || {
+                    for _ in 0..2000 {
+                        gpio.write_pin(true).unwrap();
+                        assert_eq!(gpio.read_output_latch().unwrap(), 1);
+                        gpio.write_pin(false).unwrap();
+                        assert_eq!(gpio.read_output_latch().unwrap(), 0);
+                    }
+                    gpio.write_pin(true).unwrap();
+                });
+            }
+        });
+        assert_eq!(regs.get(registers().swporta_dr()), 0b11_1100);
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_embedded_hal_pins() {
+        use embedded_hal::digital::{InputPin, OutputPin, StatefulOutputPin};
+
+        let regs = MemRegisters::new();
+        let mut gpio = duo_gpio(24, &regs);
+        gpio.set_high().unwrap();
+        assert!(gpio.is_set_high().unwrap());
+        assert_eq!(regs.get(registers().swporta_dr()), 1 << 24);
+
+        gpio.toggle().unwrap();
+        assert!(gpio.is_set_low().unwrap());
+
+        regs.set(registers().ext_porta(), 1 << 24);
+        assert!(gpio.is_high().unwrap());
+    }
+}
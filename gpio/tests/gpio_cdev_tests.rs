@@ -0,0 +1,246 @@
+// tests/gpio_cdev_tests.rs
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use gpio::gpio_cdev::{
+    GpioChipInfo, GpioChipOps, GpioV2LineConfig, GpioV2LineEvent, GpioV2LineRequest,
+    GpioV2LineValues,
+};
+use gpio::Result;
+use mockall::mock;
+
+mock! {
+    pub GpioChipOps {}
+    impl GpioChipOps for GpioChipOps {
+        fn chip_info(&self) -> Result<GpioChipInfo>;
+        fn get_line(&self, request: &mut GpioV2LineRequest) -> Result<RawFd>;
+        fn set_config(&self, line_fd: RawFd, config: &GpioV2LineConfig) -> Result<()>;
+        fn get_values(&self, line_fd: RawFd, values: &mut GpioV2LineValues) -> Result<()>;
+        fn set_values(&self, line_fd: RawFd, values: &GpioV2LineValues) -> Result<()>;
+        fn read_event(
+            &self,
+            line_fd: RawFd,
+            timeout: Option<Duration>,
+        ) -> Result<Option<GpioV2LineEvent>>;
+        fn release(&self, line_fd: RawFd) -> Result<()>;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use gpio::gpio_cdev::*;
+    use gpio::{Edge, GpioError};
+    use mockall::predicate;
+
+    use super::*;
+
+    fn expect_release(mock: &mut MockGpioChipOps, fd: RawFd) {
+        mock.expect_release().with(predicate::eq(fd)).times(1).returning(|_| Ok(()));
+    }
+
+    #[test]
+    fn test_uapi_layout() {
+        assert_eq!(size_of::<GpioChipInfo>(), 68);
+        assert_eq!(size_of::<GpioV2LineValues>(), 16);
+        assert_eq!(size_of::<GpioV2LineConfig>(), 272);
+        assert_eq!(size_of::<GpioV2LineRequest>(), 592);
+        assert_eq!(size_of::<GpioV2LineEvent>(), 48);
+        assert_eq!(GPIO_V2_GET_LINE_IOCTL, 0xc250_b407);
+        assert_eq!(GPIO_V2_LINE_SET_VALUES_IOCTL, 0xc010_b40f);
+    }
+
+    #[test]
+    fn test_request_input_line() {
+        let mut mock = MockGpioChipOps::new();
+        mock.expect_get_line()
+            .withf(|request| {
+                request.num_lines == 1
+                    && request.offsets[0] == 24
+                    && request.consumer() == "door-sensor"
+                    && request.config.flags
+                        == GPIO_V2_LINE_FLAG_INPUT
+                            | GPIO_V2_LINE_FLAG_EDGE_RISING
+                            | GPIO_V2_LINE_FLAG_EDGE_FALLING
+                            | GPIO_V2_LINE_FLAG_BIAS_PULL_UP
+                            | GPIO_V2_LINE_FLAG_ACTIVE_LOW
+                    && request.config.num_attrs == 1
+                    && request.config.attrs[0].attr.id == GPIO_V2_LINE_ATTR_ID_DEBOUNCE
+                    && request.config.attrs[0].attr.value == 5000
+                    && request.config.attrs[0].mask == 1
+            })
+            .times(1)
+            .returning(|_| Ok(7));
+        expect_release(&mut mock, 7);
+
+        let settings = LineSettings {
+            active_low: true,
+            bias: Bias::PullUp,
+            edge: Edge::Both,
+            debounce: Some(Duration::from_millis(5)),
+            ..LineSettings::input()
+        };
+        let chip = GpioCdev::new(mock);
+        let line = chip.request_line(24, "door-sensor", &settings).unwrap();
+        assert_eq!(line.offsets(), &[24]);
+    }
+
+    #[test]
+    fn test_request_output_lines() {
+        let mut mock = MockGpioChipOps::new();
+        mock.expect_get_line()
+            .withf(|request| {
+                request.num_lines == 3
+                    && request.offsets[..3] == [1, 2, 3]
+                    && request.config.flags
+                        == GPIO_V2_LINE_FLAG_OUTPUT | GPIO_V2_LINE_FLAG_OPEN_DRAIN
+                    && request.config.num_attrs == 1
+                    && request.config.attrs[0].attr.id == GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES
+                    && request.config.attrs[0].attr.value == 0b111
+                    && request.config.attrs[0].mask == 0b111
+            })
+            .times(1)
+            .returning(|_| Ok(8));
+        mock.expect_set_values()
+            .withf(|fd, values| *fd == 8 && values.mask == 0b010 && values.bits == 0)
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock.expect_get_values()
+            .withf(|fd, values| *fd == 8 && values.mask == 0b111)
+            .times(1)
+            .returning(|_, values| {
+                values.bits = 0b101;
+                Ok(())
+            });
+        expect_release(&mut mock, 8);
+
+        let settings = LineSettings { drive: Drive::OpenDrain, ..LineSettings::output(true) };
+        let chip = GpioCdev::new(mock);
+        let lines = chip.request_lines(&[1, 2, 3], "bus", &settings).unwrap();
+        lines.set_values(0b010, 0).unwrap();
+        assert_eq!(lines.get_values().unwrap(), 0b101);
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let mut mock = MockGpioChipOps::new();
+        mock.expect_get_line().times(1).returning(|_| Ok(9));
+        mock.expect_set_config()
+            .withf(|fd, config| {
+                *fd == 9
+                    && config.flags == GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN
+                    && config.num_attrs == 0
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        expect_release(&mut mock, 9);
+
+        let chip = GpioCdev::new(mock);
+        let line = chip.request_line(0, "relay", &LineSettings::output(false)).unwrap();
+        line.reconfigure(&LineSettings { bias: Bias::PullDown, ..LineSettings::input() }).unwrap();
+    }
+
+    #[test]
+    fn test_read_event() {
+        let mut mock = MockGpioChipOps::new();
+        mock.expect_get_line().times(1).returning(|_| Ok(10));
+        mock.expect_read_event()
+            .with(predicate::eq(10), predicate::eq(Some(Duration::from_secs(1))))
+            .times(1)
+            .returning(|_, _| {
+                Ok(Some(GpioV2LineEvent {
+                    timestamp_ns: 1_500_000_000,
+                    id: GPIO_V2_LINE_EVENT_FALLING_EDGE,
+                    offset: 14,
+                    seqno: 3,
+                    line_seqno: 2,
+                    ..Default::default()
+                }))
+            });
+        mock.expect_read_event().times(1).returning(|_, _| Ok(None));
+        expect_release(&mut mock, 10);
+
+        let chip = GpioCdev::new(mock);
+        let settings = LineSettings { edge: Edge::Falling, ..LineSettings::input() };
+        let line = chip.request_line(14, "button", &settings).unwrap();
+
+        let event = line.read_event(Some(Duration::from_secs(1))).unwrap().unwrap();
+        assert_eq!(event.kind, LineEventKind::FallingEdge);
+        assert_eq!(event.offset, 14);
+        assert_eq!(event.timestamp, Duration::from_millis(1500));
+        assert_eq!((event.seqno, event.line_seqno), (3, 2));
+
+        assert!(line.read_event(Some(Duration::ZERO)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_line_count() {
+        let chip = GpioCdev::new(MockGpioChipOps::new());
+        let err = chip.request_lines(&[], "empty", &LineSettings::input()).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidLineCount { count: 0 }));
+    }
+
+    #[cfg(feature = "async")]
+    mod wait {
+        use std::io::Write;
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::net::UnixStream;
+
+        use embedded_hal_async::digital::Wait;
+
+        use super::*;
+
+        /// A mock chip whose line fd is one end of a socket pair, so the
+        /// reactor sees real readiness when the other end is written.
+        fn async_chip(fd: RawFd, events: Vec<Option<GpioV2LineEvent>>) -> MockGpioChipOps {
+            let mut mock = MockGpioChipOps::new();
+            mock.expect_get_line().times(1).returning(move |_| Ok(fd));
+            mock.expect_set_config()
+                .withf(move |line_fd, config| {
+                    *line_fd == fd && config.flags & GPIO_V2_LINE_FLAG_EDGE_RISING != 0
+                })
+                .times(1)
+                .returning(|_, _| Ok(()));
+            mock.expect_set_config()
+                .withf(move |line_fd, config| {
+                    *line_fd == fd && config.flags == GPIO_V2_LINE_FLAG_INPUT
+                })
+                .times(1)
+                .returning(|_, _| Ok(()));
+            for event in events {
+                mock.expect_read_event().times(1).returning(move |_, _| Ok(event));
+            }
+            expect_release(&mut mock, fd);
+            mock
+        }
+
+        #[tokio::test]
+        async fn test_wait_for_rising_edge() {
+            let (line_end, mut other) = UnixStream::pair().unwrap();
+            let rising = GpioV2LineEvent {
+                id: GPIO_V2_LINE_EVENT_RISING_EDGE,
+                offset: 14,
+                ..Default::default()
+            };
+            // A stale event is drained when arming, then the edge arrives.
+            let mock = async_chip(line_end.as_raw_fd(), vec![Some(rising), None, Some(rising)]);
+
+            let chip = GpioCdev::new(mock);
+            let mut line = chip.request_async_line(14, "button", &LineSettings::input()).unwrap();
+            other.write_all(&[1]).unwrap();
+            line.wait_for_rising_edge().await.unwrap();
+        }
+
+        #[tokio::test]
+        async fn test_cancelled_wait_restores_settings() {
+            let (line_end, _other) = UnixStream::pair().unwrap();
+            let mock = async_chip(line_end.as_raw_fd(), vec![None]);
+
+            let chip = GpioCdev::new(mock);
+            let mut line = chip.request_async_line(14, "button", &LineSettings::input()).unwrap();
+            let wait = line.wait_for_rising_edge();
+            assert!(tokio::time::timeout(Duration::from_millis(10), wait).await.is_err());
+        }
+    }
+}
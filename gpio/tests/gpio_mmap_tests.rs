@@ -0,0 +1,53 @@
+// tests/gpio_mmap_tests.rs
+use std::fs;
+use std::path::PathBuf;
+
+/// A two-page file of zeroes standing in for `/dev/mem`.
+fn fake_dev_mem(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("duo-mmap-tests-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, vec![0; 8192]).unwrap();
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use gpio::gpio_mmap::DevMem;
+    use gpio::{GpioError, RegisterIo};
+
+    use super::*;
+
+    #[test]
+    fn test_dev_mem_round_trip() {
+        let path = fake_dev_mem("dev-mem");
+        let dev = DevMem::open(&path).unwrap();
+
+        dev.write32(0x1004, 0xdead_beef).unwrap();
+        assert_eq!(dev.read32(0x1004).unwrap(), 0xdead_beef);
+        drop(dev);
+
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(contents[0x1004..0x1008], 0xdead_beef_u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_region_outlives_dev_mem() {
+        let path = fake_dev_mem("region");
+        // Mapping through a temporary DevMem, the way MmioRegion::map does,
+        // closes its file while the region stays mapped.
+        let region = DevMem::open(&path).unwrap().map_region(0x1010, 0x20).unwrap();
+        region.write32(0x1014, 0x1234_5678).unwrap();
+        assert_eq!(region.read32(0x1014).unwrap(), 0x1234_5678);
+        assert!(matches!(region.read32(0x1030), Err(GpioError::OutOfRange { .. })));
+        assert!(matches!(region.read32(0x1016), Err(GpioError::Unaligned { addr: 0x1016 })));
+        drop(region);
+
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(contents[0x1014..0x1018], 0x1234_5678_u32.to_ne_bytes());
+
+        // The file can be mapped again once the region is gone.
+        let dev = DevMem::open(&path).unwrap();
+        assert_eq!(dev.read32(0x1014).unwrap(), 0x1234_5678);
+    }
+}
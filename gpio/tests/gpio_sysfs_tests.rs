@@ -1,8 +1,8 @@
 // tests/gpio_sysfs_tests.rs
 use std::path::Path;
+use std::time::Duration;
 
-use anyhow::Result;
-use gpio::FileSystemOps;
+use gpio::{FileSystemOps, GpioError, GpioPort, Result};
 use mockall::mock;
 use mockall::predicate::*;
 
@@ -12,12 +12,14 @@ mock! {
     impl FileSystemOps for FileSystemOps {
         fn write(&self, path: &Path, content: &[u8]) -> Result<()>;
         fn read_to_string(&self, path: &Path) -> Result<String>;
+        fn poll_priority(&self, path: &Path, timeout: Option<Duration>) -> Result<bool>;
     }
 }
 
 #[cfg(test)]
 mod tests {
     use gpio::gpio_sysfs::GpioSysfs;
+    use gpio::{DropPolicy, Edge, Trigger};
     use mockall::predicate;
 
     use super::*;
@@ -29,14 +31,14 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/export")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
         mock_fs
             .expect_write()
             .with(
-                predicate::eq(Path::new("/sys/class/gpio/gpio17/direction")),
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
                 predicate::eq("in".as_bytes()),
             )
             .times(1)
@@ -45,12 +47,12 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/unexport")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
 
-        let _ = GpioSysfs::new(17, mock_fs).unwrap();
+        let _ = GpioSysfs::new(440, mock_fs).unwrap();
     }
 
     #[test]
@@ -60,14 +62,14 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/export")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
         mock_fs
             .expect_write()
             .with(
-                predicate::eq(Path::new("/sys/class/gpio/gpio17/direction")),
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
                 predicate::eq("in".as_bytes()),
             )
             .times(2)
@@ -76,12 +78,12 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/unexport")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
 
-        let gpio = GpioSysfs::new(17, mock_fs).unwrap();
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
         gpio.set_pin_mode_input().unwrap();
     }
 
@@ -92,14 +94,14 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/export")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
         mock_fs
             .expect_write()
             .with(
-                predicate::eq(Path::new("/sys/class/gpio/gpio17/value")),
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/value")),
                 predicate::eq("1".as_bytes()),
             )
             .times(1)
@@ -107,7 +109,7 @@ mod tests {
         mock_fs
             .expect_write()
             .with(
-                predicate::eq(Path::new("/sys/class/gpio/gpio17/direction")),
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
                 predicate::eq("in".as_bytes()),
             )
             .times(1)
@@ -116,12 +118,12 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/unexport")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
 
-        let gpio = GpioSysfs::new(17, mock_fs).unwrap();
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
         gpio.write_gpio_value(1).unwrap();
     }
 
@@ -132,33 +134,33 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/export")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
         mock_fs
             .expect_write()
             .with(
-                predicate::eq(Path::new("/sys/class/gpio/gpio17/direction")),
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
                 predicate::eq("in".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
         mock_fs
             .expect_read_to_string()
-            .with(predicate::eq(Path::new("/sys/class/gpio/gpio17/value")))
+            .with(predicate::eq(Path::new("/sys/class/gpio/gpio440/value")))
             .times(1)
             .returning(|_| Ok("1".to_string()));
         mock_fs
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/unexport")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
 
-        let gpio = GpioSysfs::new(17, mock_fs).unwrap();
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
         let value = gpio.read_gpio_value().unwrap();
         assert_eq!(value, "1");
     }
@@ -170,14 +172,14 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/export")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(1)
             .returning(|_, _| Ok(()));
         mock_fs
             .expect_write()
             .with(
-                predicate::eq(Path::new("/sys/class/gpio/gpio17/direction")),
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
                 predicate::eq("in".as_bytes()),
             )
             .times(1)
@@ -186,12 +188,224 @@ mod tests {
             .expect_write()
             .with(
                 predicate::eq(Path::new("/sys/class/gpio/unexport")),
-                predicate::eq("17".as_bytes()),
+                predicate::eq("440".as_bytes()),
             )
             .times(2) // Expecting two calls to unexport, one explicitly and one from the drop
             .returning(|_, _| Ok(()));
 
-        let gpio = GpioSysfs::new(17, mock_fs).unwrap();
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
         gpio.unexport_gpio().unwrap();
     }
+
+    #[test]
+    fn test_reopen_after_leave_as_is() {
+        // The first run exports the line and leaves it driven on exit.
+        let mut mock_fs = MockFileSystemOps::new();
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/export")),
+                predicate::eq("440".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+        assert!(!gpio.is_adopted());
+        drop(gpio.with_drop_policy(DropPolicy::LeaveAsIs));
+
+        // The restarted run finds it exported and adopts it without writing
+        // direction or value; Restore leaves it exported.
+        for (policy, resets) in [(DropPolicy::Restore, 0), (DropPolicy::ResetToInput, 1)] {
+            let mut mock_fs = MockFileSystemOps::new();
+            mock_fs
+                .expect_write()
+                .with(
+                    predicate::eq(Path::new("/sys/class/gpio/export")),
+                    predicate::eq("440".as_bytes()),
+                )
+                .times(1)
+                .returning(|path, _| {
+                    Err(GpioError::sysfs(path, std::io::Error::from_raw_os_error(libc::EBUSY)))
+                });
+            mock_fs
+                .expect_write()
+                .with(
+                    predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
+                    predicate::eq("in".as_bytes()),
+                )
+                .times(resets)
+                .returning(|_, _| Ok(()));
+            mock_fs
+                .expect_write()
+                .with(predicate::eq(Path::new("/sys/class/gpio/unexport")), predicate::always())
+                .times(resets)
+                .returning(|_, _| Ok(()));
+
+            let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+            assert!(gpio.is_adopted());
+            drop(gpio.with_drop_policy(policy));
+        }
+    }
+
+    #[test]
+    fn test_invalid_gpio_is_rejected() {
+        let mock_fs = MockFileSystemOps::new();
+        let err = GpioSysfs::new(17, mock_fs).err().unwrap();
+        assert!(matches!(err, GpioError::UnknownSysfsGpio { number: 17 }));
+
+        // XGPIOC[31] is not bonded out on the CV1800B.
+        let mock_fs = MockFileSystemOps::new();
+        let err = GpioSysfs::new(447, mock_fs).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidPin { port: GpioPort::Port2, pin: 31 }));
+    }
+
+    fn expect_export_and_drop(mock_fs: &mut MockFileSystemOps) {
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/export")),
+                predicate::eq("440".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/direction")),
+                predicate::eq("in".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/unexport")),
+                predicate::eq("440".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+    }
+
+    #[test]
+    fn test_set_edge() {
+        let mut mock_fs = MockFileSystemOps::new();
+        expect_export_and_drop(&mut mock_fs);
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/edge")),
+                predicate::eq("both".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+        gpio.set_edge(Edge::Both).unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_edge() {
+        let mut mock_fs = MockFileSystemOps::new();
+        expect_export_and_drop(&mut mock_fs);
+        mock_fs
+            .expect_poll_priority()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/value")),
+                predicate::eq(Some(Duration::from_millis(100))),
+            )
+            .times(1)
+            .returning(|_, _| Ok(true));
+        mock_fs
+            .expect_read_to_string()
+            .with(predicate::eq(Path::new("/sys/class/gpio/gpio440/value")))
+            .times(1)
+            .returning(|_| Ok("0\n".to_string()));
+
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+        let event = gpio.wait_for_edge(Some(Duration::from_millis(100))).unwrap().unwrap();
+        assert_eq!(event.level, 0);
+    }
+
+    #[test]
+    fn test_wait_for_edge_timeout() {
+        let mut mock_fs = MockFileSystemOps::new();
+        expect_export_and_drop(&mut mock_fs);
+        mock_fs
+            .expect_poll_priority()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/value")),
+                predicate::eq(Some(Duration::ZERO)),
+            )
+            .times(1)
+            .returning(|_, _| Ok(false));
+        mock_fs.expect_read_to_string().never();
+
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+        assert!(gpio.wait_for_edge(Some(Duration::ZERO)).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_set_trigger() {
+        let mut mock_fs = MockFileSystemOps::new();
+        expect_export_and_drop(&mut mock_fs);
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/edge")),
+                predicate::eq("both".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+
+        let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+        gpio.set_trigger(Trigger::Both).unwrap();
+        let err = gpio.set_trigger(Trigger::Low).unwrap_err();
+        assert!(matches!(err, GpioError::UnsupportedTrigger { trigger: Trigger::Low }));
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        for (policy, unexports) in [(DropPolicy::LeaveAsIs, 0), (DropPolicy::Restore, 1)] {
+            let mut mock_fs = MockFileSystemOps::new();
+            mock_fs
+                .expect_write()
+                .with(predicate::eq(Path::new("/sys/class/gpio/export")), predicate::always())
+                .times(1)
+                .returning(|_, _| Ok(()));
+            mock_fs
+                .expect_write()
+                .with(predicate::eq(Path::new("/sys/class/gpio/unexport")), predicate::always())
+                .times(unexports)
+                .returning(|_, _| Ok(()));
+
+            // Neither policy touches the direction; an unexpected write
+            // panics in the mock.
+            let gpio = GpioSysfs::new(440, mock_fs).unwrap();
+            drop(gpio.with_drop_policy(policy));
+        }
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_embedded_hal_pins() {
+        use embedded_hal::digital::{InputPin, OutputPin};
+
+        let mut mock_fs = MockFileSystemOps::new();
+        expect_export_and_drop(&mut mock_fs);
+        mock_fs
+            .expect_write()
+            .with(
+                predicate::eq(Path::new("/sys/class/gpio/gpio440/value")),
+                predicate::eq("1".as_bytes()),
+            )
+            .times(1)
+            .returning(|_, _| Ok(()));
+        mock_fs.expect_read_to_string().times(1).returning(|_| Ok("1\n".to_string()));
+        mock_fs.expect_read_to_string().times(1).returning(|_| Ok("x\n".to_string()));
+
+        let mut gpio = GpioSysfs::new(440, mock_fs).unwrap();
+        gpio.set_high().unwrap();
+        assert!(gpio.is_high().unwrap());
+        assert!(matches!(gpio.is_low(), Err(GpioError::InvalidValue { .. })));
+    }
 }
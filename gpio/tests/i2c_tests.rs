@@ -0,0 +1,133 @@
+// tests/i2c_tests.rs
+use gpio::i2c::{FakeI2cBus, I2cAddress, I2cBus};
+
+fn bus_with(addresses: &[I2cAddress]) -> (FakeI2cBus, I2cBus<FakeI2cBus>) {
+    let fake = FakeI2cBus::new();
+    for address in addresses {
+        fake.add_device(*address);
+    }
+    (fake.clone(), I2cBus::new(fake))
+}
+
+#[cfg(test)]
+mod tests {
+    use gpio::i2c::{is_nack, I2cMessage, I2C_M_RD, I2C_M_TEN};
+    use gpio::GpioError;
+
+    use super::*;
+
+    #[test]
+    fn test_addresses() {
+        assert_eq!(I2cAddress::seven_bit(0x48).unwrap().value(), 0x48);
+        assert!(I2cAddress::ten_bit(0x3ff).unwrap().is_ten_bit());
+        assert!(matches!(
+            I2cAddress::seven_bit(0x80),
+            Err(GpioError::InvalidI2cAddress { address: 0x80 })
+        ));
+        assert!(I2cAddress::ten_bit(0x400).is_err());
+    }
+
+    #[test]
+    fn test_write_read_register() {
+        let sensor = I2cAddress::seven_bit(0x76).unwrap();
+        let (fake, bus) = bus_with(&[sensor]);
+        fake.set(sensor, 0xd0, 0x58);
+
+        let mut id = [0];
+        bus.write_read(sensor, &[0xd0], &mut id).unwrap();
+        assert_eq!(id, [0x58]);
+        assert_eq!(fake.transfers(), [vec![(0, 1), (I2C_M_RD, 1)]]);
+
+        bus.write(sensor, &[0xf4, 0x27, 0xa0]).unwrap();
+        assert_eq!(fake.get(sensor, 0xf4), Some(0x27));
+        assert_eq!(fake.get(sensor, 0xf5), Some(0xa0));
+
+        let mut buf = [0; 2];
+        bus.transaction(sensor, &mut [I2cMessage::Write(&[0xf4]), I2cMessage::Read(&mut buf)])
+            .unwrap();
+        assert_eq!(buf, [0x27, 0xa0]);
+    }
+
+    #[test]
+    fn test_ten_bit_address() {
+        let short = I2cAddress::seven_bit(0x50).unwrap();
+        let long = I2cAddress::ten_bit(0x50).unwrap();
+        let (fake, bus) = bus_with(&[long]);
+
+        bus.write(long, &[0x00, 0x11]).unwrap();
+        assert_eq!(fake.get(long, 0x00), Some(0x11));
+        assert_eq!(fake.transfers(), [vec![(I2C_M_TEN, 2)]]);
+
+        let err = bus.write(short, &[0x00, 0x11]).err().unwrap();
+        assert!(is_nack(&err));
+    }
+
+    #[test]
+    fn test_scan() {
+        let devices = [0x3c, 0x50, 0x76].map(|a| I2cAddress::seven_bit(a).unwrap());
+        let (_, bus) = bus_with(&devices);
+
+        assert_eq!(bus.scan().unwrap(), devices);
+        assert!(!bus.probe(I2cAddress::seven_bit(0x20).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_transfer_limits() {
+        let address = I2cAddress::seven_bit(0x20).unwrap();
+        let (_, bus) = bus_with(&[address]);
+
+        let err = bus.transaction(address, &mut []).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidI2cTransfer { messages: 0, .. }));
+
+        let big = vec![0; 8193];
+        let err = bus.write(address, &big).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidI2cTransfer { messages: 1, len: 8193 }));
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_embedded_hal_i2c() {
+        use embedded_hal::i2c::{Error, ErrorKind, I2c, NoAcknowledgeSource};
+
+        let address = I2cAddress::seven_bit(0x68).unwrap();
+        let (fake, mut bus) = bus_with(&[address]);
+        fake.set(address, 0x75, 0x71);
+
+        let mut who_am_i = [0];
+        I2c::write_read(&mut bus, 0x68u8, &[0x75], &mut who_am_i).unwrap();
+        assert_eq!(who_am_i, [0x71]);
+
+        let err = I2c::write(&mut bus, 0x69u8, &[0x00]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown));
+        assert!(I2c::write(&mut bus, 0x80u8, &[0x00]).is_err());
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_embedded_hal_merges_runs() {
+        use embedded_hal::i2c::{I2c, Operation};
+
+        let address = I2cAddress::seven_bit(0x50).unwrap();
+        let (fake, mut bus) = bus_with(&[address]);
+
+        // A register pointer and the data to store from it, sent as two
+        // writes with no repeated start between them.
+        I2c::transaction(&mut bus, 0x50u8, &mut [
+            Operation::Write(&[0x10]),
+            Operation::Write(&[0xaa, 0xbb, 0xcc]),
+        ])
+        .unwrap();
+        assert_eq!(fake.get(address, 0x12), Some(0xcc));
+
+        let (mut head, mut tail) = ([0; 1], [0; 2]);
+        I2c::transaction(&mut bus, 0x50u8, &mut [
+            Operation::Write(&[0x10]),
+            Operation::Read(&mut head),
+            Operation::Read(&mut tail),
+        ])
+        .unwrap();
+        assert_eq!((head, tail), ([0xaa], [0xbb, 0xcc]));
+
+        assert_eq!(fake.transfers(), [vec![(0, 4)], vec![(0, 1), (I2C_M_RD, 3)]]);
+    }
+}
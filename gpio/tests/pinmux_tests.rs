@@ -0,0 +1,108 @@
+// tests/pinmux_tests.rs
+use gpio::gpio_mmap::MemRegisters;
+use gpio::pinmux::Pinmux;
+
+fn pinmux(regs: &MemRegisters) -> Pinmux<MemRegisters> {
+    Pinmux::with_registers(regs.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use gpio::duo::MilkVDuoGpio;
+    use gpio::pinmux::{
+        Iic0SclFunction, Pad, PadBias, PadConfig, PadControl, PadFunction, Sd1D2Function, FMUX_BASE,
+    };
+    use gpio::GpioDirection::GpioInput;
+    use gpio::{Device, GpioError, GpioPort, Pin};
+
+    use super::*;
+
+    #[test]
+    fn test_read_function() {
+        let regs = MemRegisters::new();
+        regs.set(FMUX_BASE + 0x4c, 0x4);
+        let mux = pinmux(&regs);
+        assert_eq!(mux.function::<Iic0SclFunction>().unwrap(), Iic0SclFunction::Iic0Scl);
+
+        regs.set(FMUX_BASE + 0x4c, 0x6);
+        let err = mux.function::<Iic0SclFunction>().err().unwrap();
+        assert!(matches!(err, GpioError::UnknownPadFunction { pad: "IIC0_SCL", value: 6 }));
+    }
+
+    #[test]
+    fn test_set_function_preserves_upper_bits() {
+        let regs = MemRegisters::new();
+        regs.set(Pad::Sd1D2.fmux_address(), 0xf0);
+        let mux = pinmux(&regs);
+
+        mux.set_function(Sd1D2Function::Pwm5).unwrap();
+        assert_eq!(regs.get(Pad::Sd1D2.fmux_address()), 0xf7);
+        assert_eq!(mux.function::<Sd1D2Function>().unwrap(), Sd1D2Function::Pwm5);
+    }
+
+    #[test]
+    fn test_set_gpio_by_header_name() {
+        let regs = MemRegisters::new();
+        let mux = pinmux(&regs);
+        let pin = Device::Duo.pin("GP0").unwrap();
+
+        assert!(!mux.is_gpio(pin).unwrap());
+        mux.set_gpio(pin).unwrap();
+        assert!(mux.is_gpio(pin).unwrap());
+        assert_eq!(mux.function::<Iic0SclFunction>().unwrap(), Iic0SclFunction::XgpioA28);
+    }
+
+    #[test]
+    fn test_pad_table() {
+        for pad in Pad::ALL {
+            assert_eq!(Pad::for_pin(pad.gpio()), Some(*pad));
+            assert!(pad.fmux_address().is_multiple_of(4));
+        }
+        assert_eq!(Iic0SclFunction::PAD, Pad::Iic0Scl);
+        assert_eq!(Iic0SclFunction::from_bits(3), Some(Iic0SclFunction::XgpioA28));
+
+        let pin = Pin::new(GpioPort::Port2, 24).unwrap();
+        let mux = pinmux(&MemRegisters::new());
+        assert!(matches!(mux.set_gpio(pin), Err(GpioError::NoPadForPin { pin: p }) if p == pin));
+    }
+
+    #[test]
+    fn test_pad_config_bits() {
+        let core = MemRegisters::new();
+        let rtc = MemRegisters::new();
+        let pads = PadControl::with_registers(core.clone(), rtc.clone());
+
+        core.set(Pad::Iic0Scl.ioctrl_address(), 0x8000_0048);
+        pads.set_config(Pad::Iic0Scl, &PadConfig::pull_up()).unwrap();
+        assert_eq!(core.get(Pad::Iic0Scl.ioctrl_address()), 0x8000_0144);
+
+        let config = PadConfig {
+            drive_strength: 7,
+            schmitt: false,
+            slew_limit: true,
+            ..PadConfig::pull_down()
+        };
+        pads.set_config(Pad::Sd1D2, &config).unwrap();
+        assert_eq!(rtc.get(Pad::Sd1D2.ioctrl_address()), 0x8e8);
+        assert_eq!(pads.config(Pad::Sd1D2).unwrap(), config);
+
+        pads.set_bias(Pad::Sd1D2, PadBias::None).unwrap();
+        assert_eq!(rtc.get(Pad::Sd1D2.ioctrl_address()), 0x8e0);
+    }
+
+    #[test]
+    fn test_init_with_pad() {
+        let gpio_regs = MemRegisters::new();
+        let core = MemRegisters::new();
+        let pads = PadControl::with_registers(core.clone(), MemRegisters::new());
+        assert_eq!(Device::Duo.pad("GP1").unwrap(), Pad::Iic0Sda);
+
+        let pin = Device::Duo.pin("GP1").unwrap();
+        let gpio = MilkVDuoGpio::from_pin(pin, gpio_regs).unwrap();
+        gpio.init_with_pad(GpioInput, &pads, &PadConfig::pull_up()).unwrap();
+        assert_eq!(pads.config(Pad::Iic0Sda).unwrap().bias, PadBias::PullUp);
+
+        let err = Device::Duo.pad("LED").err().unwrap();
+        assert!(matches!(err, GpioError::NoPadForPin { .. }));
+    }
+}
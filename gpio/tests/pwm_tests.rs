@@ -0,0 +1,289 @@
+// tests/pwm_tests.rs
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use gpio::gpio_mmap::MemRegisters;
+use gpio::{FileSystemOps, GpioError, RegisterIo, Result};
+
+/// A sysfs tree held in memory, recording every write in order. Exporting
+/// a channel twice fails with EBUSY, like the kernel.
+#[derive(Default)]
+struct FakePwmFs {
+    files: RefCell<HashMap<PathBuf, String>>,
+    writes: RefCell<Vec<(PathBuf, String)>>,
+    exported: RefCell<HashSet<PathBuf>>,
+}
+
+impl FakePwmFs {
+    fn with_channel(dir: &str, period: u64, duty: u64) -> Self {
+        let fs = Self::default();
+        let dir = Path::new(dir);
+        fs.files.borrow_mut().insert(dir.join("period"), format!("{period}\n"));
+        fs.files.borrow_mut().insert(dir.join("duty_cycle"), format!("{duty}\n"));
+        fs
+    }
+
+    fn writes(&self) -> Vec<(String, String)> {
+        let writes = self.writes.borrow();
+        writes.iter().map(|(p, v)| (p.display().to_string(), v.clone())).collect()
+    }
+}
+
+impl FileSystemOps for &FakePwmFs {
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let content = String::from_utf8_lossy(content).into_owned();
+        let channel = path.with_file_name(format!("pwm{content}"));
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some("export") if !self.exported.borrow_mut().insert(channel.clone()) => {
+                return Err(GpioError::Sysfs {
+                    path: path.to_path_buf(),
+                    source: std::io::Error::from_raw_os_error(libc::EBUSY),
+                });
+            },
+            Some("unexport") => {
+                self.exported.borrow_mut().remove(&channel);
+            },
+            _ => {},
+        }
+        self.files.borrow_mut().insert(path.to_path_buf(), content.clone());
+        self.writes.borrow_mut().push((path.to_path_buf(), content));
+        Ok(())
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        self.files.borrow().get(path).cloned().ok_or_else(|| GpioError::Sysfs {
+            path: path.to_path_buf(),
+            source: std::io::ErrorKind::NotFound.into(),
+        })
+    }
+
+    fn poll_priority(&self, _path: &Path, _timeout: Option<Duration>) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Register memory whose reads are slow enough that an unlocked
+/// read-modify-write from another thread lands in between.
+#[derive(Clone, Default)]
+struct SlowRegisters(MemRegisters);
+
+impl RegisterIo for SlowRegisters {
+    fn map(_base: usize, _len: usize) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        let val = self.0.read32(addr);
+        std::thread::sleep(Duration::from_millis(1));
+        val
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        self.0.write32(addr, val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gpio::pinmux::{Pad, Pinmux, Sd1D2Function, Uart0TxFunction};
+    use gpio::pwm::{Pwm, PwmChannel, PwmPolarity, PwmRegisters, PwmSysfs};
+    use gpio::{Device, DropPolicy};
+
+    use super::*;
+
+    const PWM1_BASE: usize = 0x03061000;
+
+    #[test]
+    fn test_channel_numbering() {
+        let channel = PwmChannel::new(5).unwrap();
+        assert_eq!(channel.controller(), 1);
+        assert_eq!(channel.index(), 1);
+        assert_eq!(channel.sysfs_chip(), 4);
+        assert_eq!(channel.base_address(), PWM1_BASE);
+        assert!(matches!(PwmChannel::new(16), Err(GpioError::InvalidPwmChannel { channel: 16 })));
+    }
+
+    #[test]
+    fn test_sysfs_lifecycle() {
+        let fs = FakePwmFs::with_channel("/sys/class/pwm/pwmchip4/pwm1", 0, 0);
+        let pwm = PwmSysfs::new(PwmChannel::new(5).unwrap(), &fs).unwrap();
+        pwm.set_frequency(1000.0).unwrap();
+        pwm.set_duty_fraction(0.25).unwrap();
+        pwm.set_polarity(PwmPolarity::Inversed).unwrap();
+        pwm.enable().unwrap();
+        assert_eq!(pwm.duty_cycle().unwrap(), Duration::from_micros(250));
+        drop(pwm);
+
+        let dir = "/sys/class/pwm/pwmchip4/pwm1";
+        let expected = [
+            ("/sys/class/pwm/pwmchip4/export".to_string(), "1"),
+            (format!("{dir}/period"), "1000000"),
+            (format!("{dir}/duty_cycle"), "250000"),
+            (format!("{dir}/polarity"), "inversed"),
+            (format!("{dir}/enable"), "1"),
+            (format!("{dir}/enable"), "0"),
+            ("/sys/class/pwm/pwmchip4/unexport".to_string(), "1"),
+        ];
+        let expected: Vec<_> = expected.into_iter().map(|(p, v)| (p, v.to_string())).collect();
+        assert_eq!(fs.writes(), expected);
+    }
+
+    #[test]
+    fn test_sysfs_shorter_period_lowers_duty_first() {
+        let dir = "/sys/class/pwm/pwmchip0/pwm2";
+        let fs = FakePwmFs::with_channel(dir, 1_000_000, 800_000);
+        let pwm = PwmSysfs::new(PwmChannel::new(2).unwrap(), &fs)
+            .unwrap()
+            .with_drop_policy(DropPolicy::LeaveAsIs);
+        pwm.set_period(Duration::from_micros(500)).unwrap();
+
+        let writes = fs.writes();
+        assert_eq!(writes[1], (format!("{dir}/duty_cycle"), "500000".to_string()));
+        assert_eq!(writes[2], (format!("{dir}/period"), "500000".to_string()));
+
+        let err = pwm.set_duty_cycle(Duration::from_millis(1)).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidPwmDuty { .. }));
+    }
+
+    #[test]
+    fn test_registers() {
+        let regs = MemRegisters::new();
+        let pwm = PwmRegisters::with_registers(PwmChannel::new(5).unwrap(), regs.clone());
+
+        pwm.set_frequency(10_000.0).unwrap();
+        pwm.set_duty_fraction(0.3).unwrap();
+        pwm.enable().unwrap();
+        assert_eq!(regs.get(PWM1_BASE + 0x0c), 10_000);
+        assert_eq!(regs.get(PWM1_BASE + 0x08), 7_000);
+        assert_eq!(regs.get(PWM1_BASE + 0x44), 0b10);
+        assert_eq!(regs.get(PWM1_BASE + 0xd0), 0b10);
+        assert_eq!(regs.get(PWM1_BASE + 0x4c), 0);
+        assert_eq!(pwm.frequency().unwrap(), 10_000.0);
+        assert!((pwm.duty_fraction().unwrap() - 0.3).abs() < 1e-9);
+
+        pwm.set_period(Duration::from_micros(50)).unwrap();
+        assert_eq!(pwm.duty_cycle().unwrap(), Duration::from_micros(30));
+        pwm.set_polarity(PwmPolarity::Inversed).unwrap();
+        assert_eq!(regs.get(PWM1_BASE + 0x40), 0b10);
+
+        let err = pwm.set_period(Duration::from_nanos(10)).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidPwmPeriod { .. }));
+
+        drop(pwm);
+        assert_eq!(regs.get(PWM1_BASE + 0x44), 0);
+    }
+
+    #[test]
+    fn test_leave_running_on_drop() {
+        let regs = MemRegisters::new();
+        let pwm = PwmRegisters::with_registers(PwmChannel::new(5).unwrap(), regs.clone());
+        pwm.enable().unwrap();
+        drop(pwm.with_drop_policy(DropPolicy::LeaveAsIs));
+        assert_eq!(regs.get(PWM1_BASE + 0x44), 0b10);
+
+        let fs = FakePwmFs::with_channel("/sys/class/pwm/pwmchip4/pwm1", 0, 0);
+        let pwm = PwmSysfs::new(PwmChannel::new(5).unwrap(), &fs).unwrap();
+        pwm.enable().unwrap();
+        drop(pwm.with_drop_policy(DropPolicy::LeaveAsIs));
+        assert_eq!(fs.writes().last().unwrap().1, "1");
+    }
+
+    #[test]
+    fn test_reopen_after_leave_running() {
+        let fs = FakePwmFs::with_channel("/sys/class/pwm/pwmchip4/pwm1", 0, 0);
+        let channel = PwmChannel::new(5).unwrap();
+        let pwm = PwmSysfs::new(channel, &fs).unwrap();
+        assert!(!pwm.is_adopted());
+        pwm.enable().unwrap();
+        drop(pwm.with_drop_policy(DropPolicy::LeaveAsIs));
+
+        // Restore leaves the adopted channel running and exported.
+        let pwm = PwmSysfs::new(channel, &fs).unwrap();
+        assert!(pwm.is_adopted());
+        let writes = fs.writes().len();
+        drop(pwm.with_drop_policy(DropPolicy::Restore));
+        assert_eq!(fs.writes().len(), writes);
+
+        // The default stops it, but leaves the export to whoever made it.
+        drop(PwmSysfs::new(channel, &fs).unwrap());
+        let writes = fs.writes();
+        assert_eq!(
+            writes.last().unwrap(),
+            &("/sys/class/pwm/pwmchip4/pwm1/enable".to_string(), "0".to_string())
+        );
+        assert!(!writes.iter().any(|(path, _)| path.ends_with("unexport")));
+    }
+
+    #[test]
+    fn test_restore_on_drop() {
+        let regs = MemRegisters::new();
+        let channel = PwmChannel::new(5).unwrap();
+
+        // Stopped when opened, so it is stopped again.
+        let pwm = PwmRegisters::with_registers(channel, regs.clone());
+        pwm.enable().unwrap();
+        drop(pwm.with_drop_policy(DropPolicy::Restore));
+        assert_eq!(regs.get(PWM1_BASE + 0x44), 0);
+
+        // Running when opened, so it keeps running.
+        regs.set(PWM1_BASE + 0x44, 0b10);
+        let pwm = PwmRegisters::with_registers(channel, regs.clone());
+        drop(pwm.with_drop_policy(DropPolicy::Restore));
+        assert_eq!(regs.get(PWM1_BASE + 0x44), 0b10);
+    }
+
+    #[test]
+    fn test_shared_registers_are_locked() {
+        use std::sync::Barrier;
+        use std::thread;
+
+        let regs = SlowRegisters::default();
+        let barrier = Barrier::new(4);
+        thread::scope(|s| {
+            for channel in 4..8 {
+                let (regs, barrier) = (regs.clone(), &barrier);
+                s.spawn(move || {
+                    let pwm = PwmRegisters::with_registers(PwmChannel::new(channel).unwrap(), regs)
+                        .with_drop_policy(DropPolicy::LeaveAsIs);
+                    barrier.wait();
+                    pwm.enable().unwrap();
+                });
+            }
+        });
+
+        assert_eq!(regs.0.get(PWM1_BASE + 0x44), 0b1111);
+        assert_eq!(regs.0.get(PWM1_BASE + 0xd0), 0b1111);
+    }
+
+    #[test]
+    fn test_mux_pin() {
+        let regs = MemRegisters::new();
+        let mux = Pinmux::with_registers(regs.clone());
+        PwmChannel::new(5).unwrap().mux_pin(&mux, Device::Duo, "GP4").unwrap();
+        assert_eq!(mux.function::<Sd1D2Function>().unwrap(), Sd1D2Function::Pwm5);
+        assert_eq!(regs.get(Pad::Sd1D2.fmux_address()), 7);
+
+        PwmChannel::new(4).unwrap().mux_pin(&mux, Device::Duo, "GP12").unwrap();
+        assert_eq!(mux.function::<Uart0TxFunction>().unwrap(), Uart0TxFunction::Pwm4);
+        assert_eq!(regs.get(Pad::Uart0Tx.fmux_address()), 2);
+
+        let err = PwmChannel::new(4).unwrap().mux_pin(&mux, Device::Duo, "GP4").err().unwrap();
+        assert!(matches!(err, GpioError::NoPadFunction { pad: "SD1_D2", .. }));
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_set_duty_cycle_trait() {
+        use embedded_hal::pwm::SetDutyCycle;
+
+        let regs = MemRegisters::new();
+        let mut pwm = PwmRegisters::with_registers(PwmChannel::new(0).unwrap(), regs.clone());
+        pwm.set_period(Duration::from_micros(100)).unwrap();
+        SetDutyCycle::set_duty_cycle_percent(&mut pwm, 50).unwrap();
+        assert_eq!(pwm.duty_cycle().unwrap(), Duration::from_micros(50));
+        pwm.set_duty_cycle_fully_on().unwrap();
+        assert_eq!(regs.get(0x03060000), 0);
+    }
+}
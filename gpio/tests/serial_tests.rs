@@ -0,0 +1,169 @@
+// tests/serial_tests.rs
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use gpio::claim::ClaimScope;
+use gpio::gpio_mmap::MemRegisters;
+use gpio::{RegisterIo, Result};
+
+/// Opens a pseudo-terminal, returning the master and the slave's path.
+fn pty_pair() -> (File, PathBuf) {
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        assert!(master >= 0, "posix_openpt failed");
+        assert_eq!(libc::grantpt(master), 0);
+        assert_eq!(libc::unlockpt(master), 0);
+
+        let mut name = [0 as libc::c_char; 64];
+        assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+        let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().into();
+
+        (File::from_raw_fd(master), path)
+    }
+}
+
+#[derive(Clone, Default)]
+struct RecordingRegisters {
+    regs: MemRegisters,
+    writes: Arc<Mutex<Vec<(usize, u32)>>>,
+}
+
+impl RegisterIo for RecordingRegisters {
+    fn map(_base: usize, _len: usize) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    fn read32(&self, addr: usize) -> Result<u32> {
+        self.regs.read32(addr)
+    }
+
+    fn write32(&self, addr: usize, val: u32) -> Result<()> {
+        self.writes.lock().unwrap().push((addr, val));
+        self.regs.write32(addr, val)
+    }
+
+    fn claim_scope(&self) -> Option<ClaimScope> {
+        self.regs.claim_scope()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use gpio::duo::MilkVDuoGpio;
+    use gpio::pinmux::{Iic0SclFunction, Iic0SdaFunction, Pinmux};
+    use gpio::serial::{
+        DataBits, FlowControl, Parity, Rs485Config, SerialConfig, SerialPort, StopBits, Uart,
+    };
+    use gpio::{Device, GpioError, GpioPort};
+
+    use super::*;
+
+    #[test]
+    fn test_configure_framing() {
+        let (_master, path) = pty_pair();
+        // A pty always frames 8N, so only the rest of the settings stick.
+        let config = SerialConfig {
+            baud_rate: 250_000,
+            stop_bits: StopBits::Two,
+            flow_control: FlowControl::Hardware,
+            vmin: 0,
+            vtime: 5,
+            ..SerialConfig::default()
+        };
+        let port = SerialPort::open(&path, &config).unwrap();
+        assert_eq!(port.config().unwrap(), config);
+
+        let seven_odd = SerialConfig { data_bits: DataBits::Seven, parity: Parity::Odd, ..config };
+        port.configure(&seven_odd).unwrap();
+        assert_eq!(port.config().unwrap(), config);
+
+        let gps = SerialConfig { flow_control: FlowControl::Software, ..SerialConfig::new(9600) };
+        port.configure(&gps).unwrap();
+        assert_eq!(port.config().unwrap(), gps);
+
+        let err = port.configure(&SerialConfig::new(0)).err().unwrap();
+        assert!(matches!(err, GpioError::InvalidBaudRate { baud_rate: 0 }));
+    }
+
+    #[test]
+    fn test_raw_io() {
+        let (mut master, path) = pty_pair();
+        let mut port = SerialPort::open(&path, &SerialConfig::default()).unwrap();
+
+        // Raw mode passes CR and LF through untranslated.
+        port.write_all(b"$GPGGA\r\n").unwrap();
+        let mut buf = [0; 8];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"$GPGGA\r\n");
+
+        master.write_all(&[0x01, 0x03, 0x0d]).unwrap();
+        let mut buf = [0; 3];
+        port.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x03, 0x0d]);
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let (_master, path) = pty_pair();
+        let config = SerialConfig { vmin: 0, vtime: 1, ..SerialConfig::default() };
+        let mut port = SerialPort::open(&path, &config).unwrap();
+
+        let mut buf = [0; 4];
+        assert_eq!(port.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_gpio_direction_control() {
+        let (mut master, path) = pty_pair();
+        let regs = RecordingRegisters::default();
+        let de = MilkVDuoGpio::from_pin(Device::Duo.pin("GP10").unwrap(), regs.clone()).unwrap();
+        let mut port = SerialPort::open(&path, &SerialConfig::default())
+            .unwrap()
+            .with_direction_pin(de, true)
+            .unwrap();
+
+        port.write_all(&[0x11, 0x22]).unwrap();
+        let mut buf = [0; 2];
+        master.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x11, 0x22]);
+
+        let dr = GpioPort::Port2.base_address();
+        let levels: Vec<u32> = regs
+            .writes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(addr, _)| *addr == dr)
+            .map(|(_, val)| val & (1 << 9))
+            .collect();
+        assert_eq!(levels, [0, 1 << 9, 0]);
+    }
+
+    #[test]
+    fn test_kernel_rs485_unsupported_on_pty() {
+        let (_master, path) = pty_pair();
+        let port = SerialPort::open(&path, &SerialConfig::default()).unwrap();
+
+        let config = Rs485Config { rts_on_send: true, ..Rs485Config::default() };
+        let err = port.set_kernel_rs485(Some(&config)).err().unwrap();
+        assert!(matches!(err, GpioError::Ioctl { ioctl: "TIOCSRS485", .. }));
+    }
+
+    #[test]
+    fn test_uart_pins() {
+        let regs = MemRegisters::new();
+        let mux = Pinmux::with_registers(regs.clone());
+        let uart = Uart::new(1).unwrap();
+        assert_eq!(uart.device_path(), PathBuf::from("/dev/ttyS1"));
+
+        uart.mux_pins(&mux, Device::Duo, "GP0", "GP1").unwrap();
+        assert_eq!(mux.function::<Iic0SclFunction>().unwrap(), Iic0SclFunction::Uart1Tx);
+        assert_eq!(mux.function::<Iic0SdaFunction>().unwrap(), Iic0SdaFunction::Uart1Rx);
+        assert!(matches!(Uart::new(5), Err(GpioError::InvalidUart { uart: 5 })));
+    }
+}
@@ -0,0 +1,230 @@
+// tests/sim_tests.rs
+
+use gpio::duo::{DuoGpio, MilkVDuoGpio, GPIO0_BASE};
+use gpio::sim::DwApbGpioSim;
+use gpio::GpioPort::Port0;
+use gpio::RegisterIo;
+
+fn registers() -> &'static DuoGpio {
+    DuoGpio::new(GPIO0_BASE).unwrap()
+}
+
+fn duo_gpio(pin: u32, sim: &DwApbGpioSim) -> MilkVDuoGpio<'static, DwApbGpioSim> {
+    MilkVDuoGpio::with_registers(Port0, pin, sim.clone()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use gpio::GpioDirection::{GpioInput, GpioOutput};
+    use gpio::{Gpio, IntLevelType, IntPolarity, Trigger};
+
+    use super::*;
+
+    #[test]
+    fn test_ext_porta_reflects_outputs_and_inputs() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let led = duo_gpio(24, &sim);
+        let button = duo_gpio(14, &sim);
+        led.init(GpioOutput).unwrap();
+        button.init(GpioInput).unwrap();
+
+        led.write_pin(true).unwrap();
+        sim.drive_input(14, true);
+        assert_eq!(sim.output_level(24), Some(true));
+        assert_eq!(sim.output_level(14), None);
+        assert_eq!(sim.read32(registers().ext_porta()).unwrap(), (1 << 24) | (1 << 14));
+
+        // Driving an output line externally has no effect on the pad.
+        sim.drive_input(24, false);
+        assert!(sim.pad_level(24));
+    }
+
+    #[test]
+    fn test_read_pin_sees_pad_not_latch() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let button = duo_gpio(14, &sim);
+        button.init(GpioInput).unwrap();
+        button.write_pin(true).unwrap();
+
+        sim.drive_input(14, false);
+        assert_eq!(button.read_pin().unwrap(), 0);
+        assert_eq!(button.read_output_latch().unwrap(), 1);
+
+        sim.drive_input(14, true);
+        assert_eq!(button.read_pin().unwrap(), 1);
+
+        // Once switched to output, the pad follows the latch.
+        button.write_pin(false).unwrap();
+        button.init(GpioOutput).unwrap();
+        assert_eq!(button.read_pin().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_rising_edge_latches_until_eoi() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let button = duo_gpio(14, &sim);
+        button.init(GpioInput).unwrap();
+        button.set_interrupt_level_type(IntLevelType::EdgeSensitive).unwrap();
+        button.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+        button.enable_interrupt().unwrap();
+
+        sim.drive_input(14, true);
+        sim.drive_input(14, false);
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 1 << 14);
+        assert!(sim.irq_asserted());
+
+        sim.write32(registers().porta_eoi(), 1 << 14).unwrap();
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 0);
+        assert!(!sim.irq_asserted());
+    }
+
+    #[test]
+    fn test_falling_edge_polarity() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        sim.drive_input(14, true);
+        let button = duo_gpio(14, &sim);
+        button.init(GpioInput).unwrap();
+        button.set_interrupt_polarity(IntPolarity::ActiveLow).unwrap();
+        button.set_interrupt_level_type(IntLevelType::EdgeSensitive).unwrap();
+        button.enable_interrupt().unwrap();
+
+        sim.drive_input(14, true);
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 0);
+
+        sim.drive_input(14, false);
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 1 << 14);
+    }
+
+    #[test]
+    fn test_level_interrupt_follows_pad_and_ignores_eoi() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let button = duo_gpio(15, &sim);
+        button.init(GpioInput).unwrap();
+        button.set_interrupt_level_type(IntLevelType::LevelSensitive).unwrap();
+        button.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+        button.enable_interrupt().unwrap();
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 0);
+
+        sim.drive_input(15, true);
+        sim.write32(registers().porta_eoi(), 1 << 15).unwrap();
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 1 << 15);
+
+        sim.drive_input(15, false);
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_mask_hides_intstatus_only() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let button = duo_gpio(14, &sim);
+        button.init(GpioInput).unwrap();
+        button.set_interrupt_level_type(IntLevelType::EdgeSensitive).unwrap();
+        button.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+        button.enable_interrupt().unwrap();
+        button.enable_interrupt_mask().unwrap();
+
+        sim.drive_input(14, true);
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 1 << 14);
+        assert_eq!(sim.read32(registers().intstatus()).unwrap(), 0);
+        assert!(!sim.irq_asserted());
+
+        button.disable_interrupt_mask().unwrap();
+        assert_eq!(sim.read32(registers().intstatus()).unwrap(), 1 << 14);
+    }
+
+    #[test]
+    fn test_disabled_and_output_lines_do_not_interrupt() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let led = duo_gpio(24, &sim);
+        led.init(GpioOutput).unwrap();
+        led.set_interrupt_level_type(IntLevelType::EdgeSensitive).unwrap();
+        led.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+        led.enable_interrupt().unwrap();
+        let button = duo_gpio(14, &sim);
+        button.init(GpioInput).unwrap();
+        button.set_interrupt_level_type(IntLevelType::EdgeSensitive).unwrap();
+        button.set_interrupt_polarity(IntPolarity::ActiveHigh).unwrap();
+
+        led.write_pin(true).unwrap();
+        sim.drive_input(14, true);
+        assert_eq!(sim.read32(registers().raw_intstatus()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_configure_and_clear_interrupt() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let button = duo_gpio(14, &sim);
+        button.init(GpioInput).unwrap();
+
+        // An edge seen before configuration must not show up afterwards.
+        sim.drive_input(14, true);
+        button.configure_interrupt(Trigger::Falling).unwrap();
+        assert!(!button.raw_interrupt_pending().unwrap());
+        assert_eq!(sim.read32(registers().intmask()).unwrap(), 0);
+
+        sim.drive_input(14, false);
+        assert!(button.interrupt_pending().unwrap());
+        button.enable_interrupt_mask().unwrap();
+        assert!(!button.interrupt_pending().unwrap());
+        assert!(button.raw_interrupt_pending().unwrap());
+
+        button.clear_interrupt().unwrap();
+        assert!(!button.raw_interrupt_pending().unwrap());
+    }
+
+    #[test]
+    fn test_level_trigger_clears_with_level() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let button = duo_gpio(15, &sim);
+        let other = duo_gpio(16, &sim);
+        button.init(GpioInput).unwrap();
+        other.init(GpioInput).unwrap();
+        other.configure_interrupt(Trigger::Rising).unwrap();
+        button.configure_interrupt(Trigger::Low).unwrap();
+        assert!(button.interrupt_pending().unwrap());
+
+        sim.drive_input(16, true);
+        button.clear_interrupt().unwrap();
+        assert!(button.interrupt_pending().unwrap());
+        assert!(other.interrupt_pending().unwrap());
+
+        sim.drive_input(15, true);
+        assert!(!button.interrupt_pending().unwrap());
+    }
+
+    #[test]
+    fn test_both_edges_with_hardware_bothedge() {
+        let sim = DwApbGpioSim::for_port(Port0).with_bothedge();
+        let encoder = duo_gpio(14, &sim);
+        encoder.init(GpioInput).unwrap();
+        encoder.configure_interrupt(Trigger::Both).unwrap();
+        assert!(encoder.has_bothedge().unwrap());
+        assert_eq!(sim.read32(registers().int_bothedge()).unwrap(), 1 << 14);
+
+        for level in [true, false, true] {
+            sim.drive_input(14, level);
+            assert!(encoder.interrupt_pending().unwrap());
+            encoder.clear_interrupt().unwrap();
+            assert!(!encoder.interrupt_pending().unwrap());
+        }
+
+        encoder.configure_interrupt(Trigger::Rising).unwrap();
+        assert_eq!(sim.read32(registers().int_bothedge()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_both_edges_emulated_by_polarity_flip() {
+        let sim = DwApbGpioSim::for_port(Port0);
+        let encoder = duo_gpio(15, &sim);
+        encoder.init(GpioInput).unwrap();
+        encoder.configure_interrupt(Trigger::Both).unwrap();
+        assert!(!encoder.has_bothedge().unwrap());
+
+        for level in [true, false, true, false] {
+            sim.drive_input(15, level);
+            assert!(encoder.interrupt_pending().unwrap(), "edge to {level} missed");
+            encoder.clear_interrupt().unwrap();
+            assert!(!encoder.interrupt_pending().unwrap());
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// tests/spi_tests.rs
+use gpio::duo::MilkVDuoGpio;
+use gpio::gpio_mmap::MemRegisters;
+use gpio::spi::{FakeSpiDev, Spi, SpiConfig};
+use gpio::{Device, Gpio};
+
+fn loopback(config: &SpiConfig) -> (FakeSpiDev, Spi<FakeSpiDev>) {
+    let fake = FakeSpiDev::loopback();
+    let spi = Spi::new(fake.clone(), config).unwrap();
+    (fake, spi)
+}
+
+/// An SPI device with GP13 as its chip select, in register memory.
+fn with_gpio_cs(
+    config: &SpiConfig,
+) -> (FakeSpiDev, MemRegisters, Spi<FakeSpiDev, MilkVDuoGpio<'static, MemRegisters>>) {
+    let fake = FakeSpiDev::new();
+    let regs = MemRegisters::new();
+    let pin = Device::Duo.pin("GP13").unwrap();
+    let cs = MilkVDuoGpio::from_pin(pin, regs.clone()).unwrap();
+    let spi = Spi::with_chip_select(fake.clone(), config, cs).unwrap();
+    (fake, regs, spi)
+}
+
+#[cfg(test)]
+mod tests {
+    use gpio::spi::{SpiMode, SpiTransfer, SPI_CPHA, SPI_CPOL, SPI_CS_HIGH, SPI_NO_CS};
+    use gpio::{GpioDirection, GpioError};
+
+    use super::*;
+
+    #[test]
+    fn test_configure() {
+        let config = SpiConfig {
+            mode: SpiMode::Mode3,
+            max_speed_hz: 10_000_000,
+            lsb_first: true,
+            cs_high: true,
+            ..SpiConfig::default()
+        };
+        let (fake, mut spi) = loopback(&config);
+        assert_eq!(fake.mode(), SPI_CPOL | SPI_CPHA | SPI_CS_HIGH);
+        assert!(fake.lsb_first());
+        assert_eq!(fake.bits_per_word(), 8);
+        assert_eq!(fake.max_speed_hz(), 10_000_000);
+
+        spi.set_config(&SpiConfig { mode: SpiMode::Mode1, ..SpiConfig::default() }).unwrap();
+        assert_eq!(fake.mode(), SPI_CPHA);
+        assert!(!fake.lsb_first());
+    }
+
+    #[test]
+    fn test_loopback_transfers() {
+        let (fake, spi) = loopback(&SpiConfig::default());
+
+        let mut read = [0; 3];
+        spi.transfer(&mut read, &[0xde, 0xad, 0x01]).unwrap();
+        assert_eq!(read, [0xde, 0xad, 0x01]);
+
+        let mut buf = [0x55, 0xaa];
+        spi.transfer_in_place(&mut buf).unwrap();
+        assert_eq!(buf, [0x55, 0xaa]);
+
+        let mut status = [0xff; 2];
+        spi.transaction(&mut [
+            SpiTransfer::write(&[0x9f]).with_delay(10),
+            SpiTransfer::read(&mut status),
+        ])
+        .unwrap();
+        assert_eq!(status, [0, 0]);
+        assert_eq!(fake.written(), [0xde, 0xad, 0x01, 0x55, 0xaa, 0x9f, 0, 0]);
+        assert_eq!(fake.messages(), 3);
+    }
+
+    #[test]
+    fn test_queued_response() {
+        let fake = FakeSpiDev::new();
+        fake.queue_response(&[0x12, 0x34]);
+        let spi = Spi::new(fake.clone(), &SpiConfig::default()).unwrap();
+
+        let mut buf = [0; 3];
+        spi.read(&mut buf).unwrap();
+        assert_eq!(buf, [0x12, 0x34, 0xff]);
+    }
+
+    #[test]
+    fn test_invalid_transfers() {
+        let (fake, spi) = loopback(&SpiConfig::default());
+
+        let mut read = [0; 2];
+        let err = spi.transfer(&mut read, &[1, 2, 3]).err().unwrap();
+        assert!(matches!(err, GpioError::SpiLengthMismatch { read: 2, write: 3 }));
+
+        let mut transfers: Vec<_> = (0..600).map(|_| SpiTransfer::delay(1)).collect();
+        let err = spi.transaction(&mut transfers).err().unwrap();
+        assert!(matches!(err, GpioError::TooManySpiTransfers { transfers: 600 }));
+        assert_eq!(fake.messages(), 0);
+    }
+
+    #[test]
+    fn test_gpio_chip_select() {
+        let (fake, regs, spi) = with_gpio_cs(&SpiConfig::default());
+        let cs = spi.chip_select().unwrap();
+        let dr = gpio::GpioPort::Port0.base_address();
+
+        assert_eq!(fake.mode() & SPI_NO_CS, SPI_NO_CS);
+        assert_eq!(cs.direction().unwrap(), GpioDirection::GpioOutput);
+        assert_eq!(regs.get(dr) & (1 << 17), 1 << 17);
+
+        spi.write(&[0x01]).unwrap();
+        assert_eq!(fake.written(), [0x01]);
+        assert_eq!(regs.get(dr) & (1 << 17), 1 << 17);
+
+        drop(spi);
+        let (_, regs, spi) = with_gpio_cs(&SpiConfig { cs_high: true, ..SpiConfig::default() });
+        spi.write(&[0x01]).unwrap();
+        assert_eq!(regs.get(dr) & (1 << 17), 0);
+    }
+
+    #[cfg(feature = "embedded-hal")]
+    #[test]
+    fn test_spi_device_trait() {
+        use embedded_hal::spi::{Operation, SpiDevice};
+
+        let (fake, mut spi) = loopback(&SpiConfig::default());
+        let mut read = [0; 4];
+        let mut in_place = [7, 8];
+        SpiDevice::transaction(&mut spi, &mut [
+            Operation::Transfer(&mut read, &[1, 2]),
+            Operation::DelayNs(1500),
+            Operation::Write(&[3]),
+            Operation::TransferInPlace(&mut in_place),
+        ])
+        .unwrap();
+
+        assert_eq!(read, [1, 2, 0, 0]);
+        assert_eq!(in_place, [7, 8]);
+        assert_eq!(fake.written(), [1, 2, 0, 0, 3, 7, 8]);
+        assert_eq!(fake.messages(), 1);
+    }
+}